@@ -0,0 +1,106 @@
+//! best-effort implementation for targets without a thread affinity api, binding reports
+//! [`ErrorKind::Unsupported`] and everything else degrades to what std can tell
+
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+
+/// the cpu current thread runs on is unknown off linux
+pub fn current_core() -> Option<usize> {
+    None
+}
+
+pub fn cores_online() -> usize {
+    std::thread::available_parallelism().map_or(1, |x| x.get())
+}
+
+/// every cpu std reports, there's no portable way to see a cpuset
+pub fn allowed_cpus() -> Result<Vec<usize>, Error> {
+    Ok((0..cores_online()).collect())
+}
+
+/// threads are never bound, so they may run on every allowed cpu
+pub fn current_affinity() -> Result<Vec<usize>, Error> {
+    allowed_cpus()
+}
+
+pub fn thread_affinity(_tid: usize) -> Result<Vec<usize>, Error> {
+    allowed_cpus()
+}
+
+pub fn bind_thread(_tid: usize, _cpus: &[usize]) -> Result<(), Error> {
+    Err(unsupported())
+}
+
+/// other threads of the process can't be listed portably
+pub fn threads() -> Result<Vec<usize>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "listing threads is not supported on this platform",
+    ))
+}
+
+pub fn thread_name(_tid: usize) -> Option<String> {
+    None
+}
+
+/// a no-op guard, kept so callers compile unchanged on every target
+#[must_use = "the affinity is restored when the guard is dropped"]
+pub struct BindGuard {
+    _thread: PhantomData<*const ()>,
+}
+
+impl BindGuard {
+    pub fn forget(self) {}
+}
+
+fn unsupported() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "thread affinity is not supported on this platform",
+    )
+}
+
+pub fn bind_cpus(_cpus: &[usize]) -> Result<BindGuard, Error> {
+    Err(unsupported())
+}
+
+pub fn bind_cpu(_cpu: usize) -> Result<BindGuard, Error> {
+    Err(unsupported())
+}
+
+pub fn bind_core(_id: usize) -> Result<BindGuard, Error> {
+    Err(unsupported())
+}
+
+/// nothing is ever bound, so there's nothing to undo
+pub fn unbind_core() -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn gettid() -> usize {
+    let mut id = 0u64;
+    unsafe { libc::pthread_threadid_np(0, &mut id) };
+    id as usize
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn gettid() -> usize {
+    unsafe { libc::pthread_getthreadid_np() as usize }
+}
+
+/// a process unique id handed out on first use in each thread
+#[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+pub fn gettid() -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    static G_ID: AtomicUsize = AtomicUsize::new(1);
+    thread_local! {
+        static G_TID: usize = G_ID.fetch_add(1, Relaxed);
+    }
+    G_TID.with(|x| *x)
+}
+
+pub fn supports_affinity() -> bool {
+    false
+}
@@ -1,37 +1,31 @@
-#[cfg(target_os = "linux")]
-use libc::{
-    cpu_set_t, pthread_self, pthread_setaffinity_np, sched_getcpu, sysconf, CPU_SET,
-    _SC_NPROCESSORS_ONLN,
-};
+mod sched;
+mod topology;
 
-#[cfg(target_os = "linux")]
-pub fn current_core() -> usize {
-    unsafe { sched_getcpu() as usize }
-}
+pub use sched::{sched_stat, SchedStat};
+pub use topology::{parse_cpulist, Cpu, Placement, Topology};
 
 #[cfg(target_os = "linux")]
-pub fn cores_online() -> usize {
-    unsafe { sysconf(_SC_NPROCESSORS_ONLN) as usize }
-}
-
+mod linux;
 #[cfg(target_os = "linux")]
-pub fn bind_core(id: usize) {
-    unsafe {
-        let mut set: cpu_set_t = std::mem::zeroed();
-        CPU_SET(id % cores_online(), &mut set);
-        pthread_setaffinity_np(pthread_self(), size_of::<cpu_set_t>(), &set);
-    }
-}
+pub use linux::*;
 
-#[cfg(target_os = "linux")]
-pub fn unbind_core() {
-    unsafe {
-        let mut set: cpu_set_t = std::mem::zeroed();
-        pthread_setaffinity_np(pthread_self(), std::mem::size_of::<cpu_set_t>(), &mut set);
-    }
-}
+#[cfg(not(target_os = "linux"))]
+mod fallback;
+#[cfg(not(target_os = "linux"))]
+pub use fallback::*;
 
-#[cfg(target_os = "linux")]
-pub fn gettid() -> usize {
-    unsafe { libc::gettid() as usize }
+#[cfg(test)]
+mod test {
+    use crate::{allowed_cpus, gettid, sched_stat};
+
+    #[test]
+    fn test_gettid() {
+        let id = gettid();
+        assert_eq!(id, gettid());
+        assert_ne!(id, std::thread::spawn(gettid).join().unwrap());
+        assert!(!allowed_cpus().unwrap().is_empty());
+        if cfg!(target_os = "linux") {
+            assert!(sched_stat(id).is_ok());
+        }
+    }
 }
@@ -0,0 +1,323 @@
+use libc::{
+    cpu_set_t, pid_t, pthread_getaffinity_np, pthread_self, pthread_setaffinity_np,
+    sched_getaffinity, sched_getcpu, sched_setaffinity, sysconf, _SC_NPROCESSORS_ONLN, CPU_ISSET,
+    CPU_SET, CPU_SETSIZE,
+};
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub fn current_core() -> Option<usize> {
+    let r = unsafe { sched_getcpu() };
+    (r >= 0).then_some(r as usize)
+}
+
+pub fn cores_online() -> usize {
+    unsafe { sysconf(_SC_NPROCESSORS_ONLN) as usize }
+}
+
+fn to_set(cpus: &[usize]) -> Result<cpu_set_t, Error> {
+    let mut set: cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= CPU_SETSIZE as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cpu {cpu} out of range"),
+            ));
+        }
+        unsafe { CPU_SET(cpu, &mut set) };
+    }
+    Ok(set)
+}
+
+fn from_set(set: &cpu_set_t) -> Vec<usize> {
+    (0..CPU_SETSIZE as usize)
+        .filter(|&i| unsafe { CPU_ISSET(i, set) })
+        .collect()
+}
+
+fn get_affinity() -> Result<cpu_set_t, Error> {
+    let mut set: cpu_set_t = unsafe { std::mem::zeroed() };
+    let rc = unsafe { pthread_getaffinity_np(pthread_self(), size_of::<cpu_set_t>(), &mut set) };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(rc));
+    }
+    Ok(set)
+}
+
+fn set_affinity(set: &cpu_set_t) -> Result<(), Error> {
+    let rc = unsafe { pthread_setaffinity_np(pthread_self(), size_of::<cpu_set_t>(), set) };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(rc));
+    }
+    Ok(())
+}
+
+/// affinity of the main thread the first time it's asked for, i.e. any `taskset` applied at
+/// launch, the main thread may be bound later
+static G_LAUNCH: OnceLock<Vec<usize>> = OnceLock::new();
+
+/// cpus the process may run on, the cgroup cpuset narrowed to the affinity the process was
+/// launched with, binding any thread including the main one doesn't change it, the launch
+/// affinity is taken on the first call which must come before the main thread is bound
+pub fn allowed_cpus() -> Result<Vec<usize>, Error> {
+    let launch = match G_LAUNCH.get() {
+        Some(x) => x,
+        None => {
+            let x = thread_affinity(std::process::id() as usize)?;
+            G_LAUNCH.get_or_init(|| x)
+        }
+    };
+    let cpuset = std::fs::read_to_string("/proc/self/cgroup")
+        .map(|x| cpuset_files(&x))
+        .unwrap_or_default()
+        .into_iter()
+        .chain([PathBuf::from("/sys/devices/system/cpu/online")])
+        .find_map(|x| crate::parse_cpulist(&std::fs::read_to_string(x).ok()?))
+        .unwrap_or_else(|| launch.clone());
+    Ok(launch
+        .iter()
+        .copied()
+        .filter(|x| cpuset.contains(x))
+        .collect())
+}
+
+/// files which may hold the effective cpuset of the cgroup described by `cgroup`, the content
+/// of `/proc/self/cgroup`, v2 first, either mounted alone or beside v1 controllers
+fn cpuset_files(cgroup: &str) -> Vec<PathBuf> {
+    let root = PathBuf::from("/sys/fs/cgroup");
+    let mut r = Vec::new();
+    for l in cgroup.lines() {
+        let mut it = l.splitn(3, ':');
+        let (Some(_), Some(ctl), Some(path)) = (it.next(), it.next(), it.next()) else {
+            continue;
+        };
+        let path = path.trim_start_matches('/');
+        if ctl.is_empty() {
+            for x in [root.clone(), root.join("unified")] {
+                r.insert(0, x.join(path).join("cpuset.cpus.effective"));
+            }
+        } else if ctl.split(',').any(|x| x == "cpuset") {
+            let dir = root.join("cpuset").join(path);
+            r.push(dir.join("cpuset.effective_cpus"));
+            r.push(dir.join("cpuset.cpus"));
+        }
+    }
+    r
+}
+
+/// cpus thread `tid` of the process may run on
+pub fn thread_affinity(tid: usize) -> Result<Vec<usize>, Error> {
+    let mut set: cpu_set_t = unsafe { std::mem::zeroed() };
+    let rc = unsafe { sched_getaffinity(tid as pid_t, size_of::<cpu_set_t>(), &mut set) };
+    if rc != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(from_set(&set))
+}
+
+/// pin thread `tid` of the process to exactly the cpus in `cpus`, unlike [`bind_cpus`] it works
+/// on any thread, so there's no guard and restoring the old affinity is up to the caller
+pub fn bind_thread(tid: usize, cpus: &[usize]) -> Result<(), Error> {
+    let set = to_set(cpus)?;
+    let rc = unsafe { sched_setaffinity(tid as pid_t, size_of::<cpu_set_t>(), &set) };
+    if rc != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// ids of every live thread of the process, read from `/proc/self/task`, threads may exit or be
+/// spawned right after the listing
+pub fn threads() -> Result<Vec<usize>, Error> {
+    let mut r: Vec<usize> = std::fs::read_dir("/proc/self/task")?
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect();
+    r.sort_unstable();
+    Ok(r)
+}
+
+/// name of thread `tid` of the process, `None` if it has exited
+pub fn thread_name(tid: usize) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/self/task/{tid}/comm"))
+        .ok()
+        .map(|x| x.trim_end().to_string())
+}
+
+/// cpus the current thread may run on
+pub fn current_affinity() -> Result<Vec<usize>, Error> {
+    get_affinity().map(|x| from_set(&x))
+}
+
+/// restores the affinity the thread had before binding when dropped, it's `!Send` since the
+/// affinity belongs to the thread which created it
+#[must_use = "the affinity is restored when the guard is dropped"]
+pub struct BindGuard {
+    old: cpu_set_t,
+    _thread: PhantomData<*const ()>,
+}
+
+impl BindGuard {
+    /// keep the binding for the rest of the thread's life
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for BindGuard {
+    fn drop(&mut self) {
+        let _ = set_affinity(&self.old);
+    }
+}
+
+/// pin current thread to exactly the cpus in `cpus`
+pub fn bind_cpus(cpus: &[usize]) -> Result<BindGuard, Error> {
+    let set = to_set(cpus)?;
+    let old = get_affinity()?;
+    set_affinity(&set)?;
+    Ok(BindGuard {
+        old,
+        _thread: PhantomData,
+    })
+}
+
+/// pin current thread to exactly `cpu`, unlike [`bind_core`] the id is not wrapped
+pub fn bind_cpu(cpu: usize) -> Result<BindGuard, Error> {
+    bind_cpus(&[cpu])
+}
+
+/// pin current thread to the `id`-th allowed cpu, wrapping around the allowed cpus
+pub fn bind_core(id: usize) -> Result<BindGuard, Error> {
+    let cpus = allowed_cpus()?;
+    if cpus.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, "no allowed cpu"));
+    }
+    bind_cpu(cpus[id % cpus.len()])
+}
+
+/// let current thread run on every allowed cpu again
+pub fn unbind_core() -> Result<(), Error> {
+    set_affinity(&to_set(&allowed_cpus()?)?)
+}
+
+pub fn gettid() -> usize {
+    unsafe { libc::gettid() as usize }
+}
+
+pub fn supports_affinity() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        allowed_cpus, bind_core, bind_cpu, bind_thread, cpuset_files, current_affinity,
+        current_core, gettid, thread_affinity, thread_name, threads, unbind_core,
+    };
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+
+    // every test runs in its own thread so the affinity of the test harness is never touched
+
+    #[test]
+    fn test_bind_restore() {
+        std::thread::spawn(|| {
+            let old = current_affinity().unwrap();
+            let cpu = allowed_cpus().unwrap()[0];
+            let g = bind_cpu(cpu).unwrap();
+            assert_eq!(current_affinity().unwrap(), vec![cpu]);
+            assert_eq!(current_core(), Some(cpu));
+            drop(g);
+            assert_eq!(current_affinity().unwrap(), old);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bind_wrap() {
+        std::thread::spawn(|| {
+            let cpus = allowed_cpus().unwrap();
+            let g = bind_core(cpus.len() + 1).unwrap();
+            assert_eq!(current_affinity().unwrap(), vec![cpus[1 % cpus.len()]]);
+            g.forget();
+            unbind_core().unwrap();
+            assert_eq!(current_affinity().unwrap(), cpus);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bind_error() {
+        std::thread::spawn(|| {
+            let old = current_affinity().unwrap();
+            assert!(bind_cpu(libc::CPU_SETSIZE as usize).is_err());
+            // the first cpu the process is not allowed to use
+            let allowed = allowed_cpus().unwrap();
+            let denied = (0..).find(|x| !allowed.contains(x)).unwrap();
+            assert!(bind_cpu(denied).is_err());
+            assert_eq!(current_affinity().unwrap(), old);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bind_thread() {
+        let (tx, rx) = channel();
+        let (done_tx, done_rx) = channel::<()>();
+        let h = std::thread::Builder::new()
+            .name("coreid-test".into())
+            .spawn(move || {
+                tx.send(gettid()).unwrap();
+                done_rx.recv().unwrap();
+                current_affinity().unwrap()
+            })
+            .unwrap();
+        let tid = rx.recv().unwrap();
+        assert!(threads().unwrap().contains(&tid));
+        assert_eq!(thread_name(tid).as_deref(), Some("coreid-test"));
+
+        let cpu = *allowed_cpus().unwrap().last().unwrap();
+        bind_thread(tid, &[cpu]).unwrap();
+        assert_eq!(thread_affinity(tid).unwrap(), vec![cpu]);
+        done_tx.send(()).unwrap();
+        assert_eq!(h.join().unwrap(), vec![cpu]);
+        assert!(bind_thread(tid, &[cpu]).is_err());
+    }
+
+    #[test]
+    fn test_cpuset_files() {
+        assert_eq!(
+            cpuset_files("0::/a/b\n"),
+            [
+                "/sys/fs/cgroup/unified/a/b/cpuset.cpus.effective",
+                "/sys/fs/cgroup/a/b/cpuset.cpus.effective"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            cpuset_files("4:memory:/x\n3:cpuset,cpu:/c\n"),
+            [
+                "/sys/fs/cgroup/cpuset/c/cpuset.effective_cpus",
+                "/sys/fs/cgroup/cpuset/c/cpuset.cpus"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_allowed_main_bound() {
+        // binding the main thread, here the test harness' thread, must not shrink the set
+        let cpus = allowed_cpus().unwrap();
+        let pid = std::process::id() as usize;
+        let old = thread_affinity(pid).unwrap();
+        bind_thread(pid, &cpus[..1]).unwrap();
+        let now = allowed_cpus().unwrap();
+        bind_thread(pid, &old).unwrap();
+        assert_eq!(now, cpus);
+    }
+}
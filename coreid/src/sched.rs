@@ -0,0 +1,106 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+/// scheduler accounting of a thread, from `/proc/self/task/<tid>/schedstat` and
+/// `/proc/self/task/<tid>/sched`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedStat {
+    /// time spent on a cpu
+    pub run: Duration,
+    /// time spent runnable on a run queue waiting for a cpu
+    pub wait: Duration,
+    /// number of times the thread got a cpu
+    pub slices: u64,
+    /// cpu migrations, `None` if the kernel doesn't expose the `sched` file
+    pub migrations: Option<u64>,
+    pub voluntary_switches: Option<u64>,
+    pub involuntary_switches: Option<u64>,
+}
+
+impl SchedStat {
+    /// parse the content of `schedstat` and optionally `sched`
+    pub fn parse(schedstat: &str, sched: Option<&str>) -> Option<Self> {
+        let mut it = schedstat.split_whitespace().map(|x| x.parse::<u64>().ok());
+        let (run, wait, slices) = (it.next()??, it.next()??, it.next()??);
+        let field = |name: &str| {
+            sched?.lines().find_map(|l| {
+                let (k, v) = l.split_once(':')?;
+                (k.trim() == name).then(|| v.trim().parse().ok())?
+            })
+        };
+        Some(Self {
+            run: Duration::from_nanos(run),
+            wait: Duration::from_nanos(wait),
+            slices,
+            migrations: field("se.nr_migrations"),
+            voluntary_switches: field("nr_voluntary_switches"),
+            involuntary_switches: field("nr_involuntary_switches"),
+        })
+    }
+
+    /// what happened between `before` and `self`, counters missing in either are `None`
+    pub fn since(&self, before: &Self) -> Self {
+        let sub = |a: Option<u64>, b: Option<u64>| Some(a?.saturating_sub(b?));
+        Self {
+            run: self.run.saturating_sub(before.run),
+            wait: self.wait.saturating_sub(before.wait),
+            slices: self.slices.saturating_sub(before.slices),
+            migrations: sub(self.migrations, before.migrations),
+            voluntary_switches: sub(self.voluntary_switches, before.voluntary_switches),
+            involuntary_switches: sub(self.involuntary_switches, before.involuntary_switches),
+        }
+    }
+}
+
+/// scheduler accounting of thread `tid` of the process, only linux with schedstats support
+/// provides it, elsewhere it's [`ErrorKind::Unsupported`]
+pub fn sched_stat(tid: usize) -> Result<SchedStat, Error> {
+    if !cfg!(target_os = "linux") {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "scheduler statistics are not supported on this platform",
+        ));
+    }
+    let dir = format!("/proc/self/task/{tid}");
+    let schedstat = std::fs::read_to_string(format!("{dir}/schedstat"))?;
+    let sched = std::fs::read_to_string(format!("{dir}/sched")).ok();
+    SchedStat::parse(&schedstat, sched.as_deref()).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("bad schedstat of thread {tid}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::SchedStat;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse() {
+        let sched = "kv_bench (42, #threads: 3)\n\
+            -------------------------------------------------------------------\n\
+            se.nr_migrations                             :                    5\n\
+            nr_switches                                  :                   10\n\
+            nr_voluntary_switches                        :                    7\n\
+            nr_involuntary_switches                      :                    3\n";
+        let a = SchedStat::parse("1000 200 10\n", Some(sched)).unwrap();
+        assert_eq!(a.run, Duration::from_nanos(1000));
+        assert_eq!(a.wait, Duration::from_nanos(200));
+        assert_eq!(a.slices, 10);
+        assert_eq!(a.migrations, Some(5));
+        assert_eq!(a.voluntary_switches, Some(7));
+        assert_eq!(a.involuntary_switches, Some(3));
+
+        let b = SchedStat::parse("4000 300 12", None).unwrap();
+        assert_eq!(b.migrations, None);
+        let d = b.since(&a);
+        assert_eq!(d.run, Duration::from_nanos(3000));
+        assert_eq!(d.slices, 2);
+        assert_eq!(d.migrations, None);
+
+        assert_eq!(SchedStat::parse("1 2", None), None);
+        assert_eq!(SchedStat::parse("a b c", None), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// a logical cpu and where it sits in the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub id: usize,
+    /// physical core id, unique within a package, hyperthreads share it
+    pub core: usize,
+    pub package: usize,
+    pub node: usize,
+}
+
+/// cpu topology of the machine, parsed from `/sys/devices/system/cpu` and
+/// `/sys/devices/system/node`
+#[derive(Debug, Clone)]
+pub struct Topology {
+    cpus: Vec<Cpu>,
+}
+
+/// how worker threads are spread over cpus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// fill one node before the next, hyperthread siblings are adjacent
+    Compact,
+    /// round robin across nodes, physical cores of a node are used before hyperthreads
+    Scatter,
+    /// one thread per physical core, hyperthread siblings are only used when every physical
+    /// core is taken
+    Physical,
+    /// explicit cpu list, e.g. `0-3,8,10-11`
+    List(Vec<usize>),
+}
+
+impl Placement {
+    /// parse `compact`, `scatter`, `physical` or a cpulist
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "compact" => Some(Self::Compact),
+            "scatter" => Some(Self::Scatter),
+            "physical" => Some(Self::Physical),
+            _ => parse_cpulist(s).filter(|x| !x.is_empty()).map(Self::List),
+        }
+    }
+}
+
+/// parse the kernel's cpulist format, e.g. `0-3,8,10-11`
+pub fn parse_cpulist(s: &str) -> Option<Vec<usize>> {
+    let mut r = Vec::new();
+    for part in s.trim().split(',').filter(|x| !x.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let (lo, hi): (usize, usize) = (lo.trim().parse().ok()?, hi.trim().parse().ok()?);
+                if lo > hi {
+                    return None;
+                }
+                r.extend(lo..=hi);
+            }
+            None => r.push(part.trim().parse().ok()?),
+        }
+    }
+    Some(r)
+}
+
+fn read_list(path: &Path) -> Result<Vec<usize>, Error> {
+    let s = std::fs::read_to_string(path)?;
+    parse_cpulist(&s).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("bad cpulist in {}", path.display()),
+        )
+    })
+}
+
+fn read_id(path: &Path) -> Result<usize, Error> {
+    let s = std::fs::read_to_string(path)?;
+    // some virtual machines report -1 for the package
+    let id: i64 = s.trim().parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("bad id in {}", path.display()),
+        )
+    })?;
+    Ok(id.max(0) as usize)
+}
+
+impl Topology {
+    /// parse sysfs on linux, elsewhere every cpu is assumed to be its own core on node 0
+    pub fn detect() -> Result<Self, Error> {
+        if cfg!(target_os = "linux") {
+            Self::from_sysfs(Path::new("/sys/devices/system"))
+        } else {
+            Ok(Self::from_cpus(
+                (0..crate::cores_online())
+                    .map(|id| Cpu {
+                        id,
+                        core: id,
+                        package: 0,
+                        node: 0,
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    /// parse from a sysfs root which contains `cpu` and optionally `node`
+    pub fn from_sysfs(root: &Path) -> Result<Self, Error> {
+        let cpu_root = root.join("cpu");
+        let mut cpus = Vec::new();
+        for id in read_list(&cpu_root.join("online"))? {
+            let topo = cpu_root.join(format!("cpu{id}/topology"));
+            cpus.push(Cpu {
+                id,
+                core: read_id(&topo.join("core_id")).unwrap_or(id),
+                package: read_id(&topo.join("physical_package_id")).unwrap_or(0),
+                node: 0,
+            });
+        }
+
+        // kernels built without NUMA have no node directory, everything is on node 0
+        if let Ok(dir) = std::fs::read_dir(root.join("node")) {
+            for e in dir.flatten() {
+                let name = e.file_name();
+                let Some(node) = name
+                    .to_str()
+                    .and_then(|x| x.strip_prefix("node"))
+                    .and_then(|x| x.parse().ok())
+                else {
+                    continue;
+                };
+                for id in read_list(&e.path().join("cpulist"))? {
+                    if let Some(c) = cpus.iter_mut().find(|c| c.id == id) {
+                        c.node = node;
+                    }
+                }
+            }
+        }
+
+        if cpus.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "no online cpu"));
+        }
+        Ok(Self { cpus })
+    }
+
+    pub fn from_cpus(cpus: Vec<Cpu>) -> Self {
+        Self { cpus }
+    }
+
+    pub fn cpus(&self) -> &[Cpu] {
+        &self.cpus
+    }
+
+    /// sorted node ids
+    pub fn nodes(&self) -> Vec<usize> {
+        let mut r: Vec<_> = self.cpus.iter().map(|c| c.node).collect();
+        r.sort_unstable();
+        r.dedup();
+        r
+    }
+
+    /// cpu ids ordered by node, then physical core, then hyperthread
+    fn compact(&self) -> Vec<Cpu> {
+        let mut r = self.cpus.clone();
+        r.sort_unstable_by_key(|c| (c.node, c.package, c.core, c.id));
+        r
+    }
+
+    /// compact order with the first hyperthread of every physical core moved ahead of the rest
+    fn physical(&self) -> Vec<Cpu> {
+        let cpus = self.compact();
+        let (mut first, mut rest) = (Vec::new(), Vec::new());
+        for (i, c) in cpus.iter().enumerate() {
+            let sibling = i > 0 && {
+                let p = &cpus[i - 1];
+                (p.node, p.package, p.core) == (c.node, c.package, c.core)
+            };
+            if sibling {
+                rest.push(*c);
+            } else {
+                first.push(*c);
+            }
+        }
+        first.extend(rest);
+        first
+    }
+
+    /// cpus of each node keeping the order of `cpus`, nodes sorted by id
+    fn by_node(&self, cpus: &[Cpu]) -> Vec<Vec<usize>> {
+        self.nodes()
+            .into_iter()
+            .map(|n| cpus.iter().filter(|c| c.node == n).map(|c| c.id).collect())
+            .collect()
+    }
+
+    /// the cpu of each of `n` threads, cpus are reused in the same order when `n` exceeds the
+    /// number the policy can offer
+    pub fn place(&self, policy: &Placement, n: usize) -> Vec<usize> {
+        let order: Vec<usize> = match policy {
+            Placement::Compact => self.compact().iter().map(|c| c.id).collect(),
+            Placement::Scatter => {
+                let nodes = self.by_node(&self.physical());
+                let max = nodes.iter().map(|x| x.len()).max().unwrap_or(0);
+                (0..max)
+                    .flat_map(|i| nodes.iter().filter_map(move |x| x.get(i).copied()))
+                    .collect()
+            }
+            Placement::Physical => self.physical().iter().map(|c| c.id).collect(),
+            Placement::List(x) => x.clone(),
+        };
+        if order.is_empty() {
+            return Vec::new();
+        }
+        (0..n).map(|i| order[i % order.len()]).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cpulist, Placement, Topology};
+    use std::path::Path;
+
+    fn write(root: &Path, path: &str, s: &str) {
+        let p = root.join(path);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(p, s).unwrap();
+    }
+
+    /// 2 nodes, each with 2 physical cores of 2 hyperthreads, numbered like intel does: the
+    /// sibling of cpu `x` is `x + 4`
+    fn fake_sysfs(root: &Path) {
+        let _ = std::fs::remove_dir_all(root);
+        write(root, "cpu/online", "0-7\n");
+        for id in 0..8 {
+            let core = id % 4;
+            write(
+                root,
+                &format!("cpu/cpu{id}/topology/core_id"),
+                &format!("{core}\n"),
+            );
+            write(
+                root,
+                &format!("cpu/cpu{id}/topology/physical_package_id"),
+                &format!("{}\n", core / 2),
+            );
+        }
+        write(root, "node/node0/cpulist", "0-1,4-5\n");
+        write(root, "node/node1/cpulist", "2-3,6-7\n");
+    }
+
+    #[test]
+    fn test_cpulist() {
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpulist(""), Some(vec![]));
+        assert_eq!(parse_cpulist("3-1"), None);
+        assert_eq!(parse_cpulist("a"), None);
+        assert_eq!(Placement::parse("1,3"), Some(Placement::List(vec![1, 3])));
+        assert_eq!(Placement::parse("bad"), None);
+    }
+
+    #[test]
+    fn test_placement() {
+        let root = std::env::temp_dir().join(format!("coreid_topo_{}", std::process::id()));
+        fake_sysfs(&root);
+        let t = Topology::from_sysfs(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(t.nodes(), vec![0, 1]);
+        assert_eq!(t.place(&Placement::Compact, 4), vec![0, 4, 1, 5]);
+        assert_eq!(t.place(&Placement::Scatter, 6), vec![0, 2, 1, 3, 4, 6]);
+        assert_eq!(t.place(&Placement::Physical, 6), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(t.place(&Placement::List(vec![7, 3]), 3), vec![7, 3, 7]);
+    }
+}
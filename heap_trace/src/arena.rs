@@ -0,0 +1,157 @@
+//! a pure-Rust bump allocator, each thread carves small blocks out of its own chunk and a chunk
+//! goes back to the system once every block of it is freed, large blocks go to the system
+//! directly
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const CHUNK: usize = 1 << 20;
+
+/// blocks larger than this or aligned more than [`MAX_ALIGN`] bypass the arena
+const MAX_SMALL: usize = CHUNK / 8;
+
+const MAX_ALIGN: usize = 4096;
+
+/// the chunk starts with its reference count, blocks follow
+const HEAD: usize = 64;
+
+static G_LIVE: AtomicUsize = AtomicUsize::new(0);
+static G_TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// current chunk of the thread and the offset of its free space, the chunk of an exited
+    /// thread is never returned since releasing it would need a destructor
+    static G_CUR: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+fn chunk_layout() -> Layout {
+    Layout::from_size_align(CHUNK, CHUNK).unwrap()
+}
+
+fn is_small(l: &Layout) -> bool {
+    l.size() <= MAX_SMALL && l.align() <= MAX_ALIGN
+}
+
+/// # Safety
+/// `chunk` is the start of a live chunk
+unsafe fn refs<'a>(chunk: usize) -> &'a AtomicUsize {
+    unsafe { &*(chunk as *const AtomicUsize) }
+}
+
+/// a chunk holds one reference per live block plus one while it's the current chunk of a
+/// thread
+unsafe fn release(chunk: usize) {
+    if unsafe { refs(chunk) }.fetch_sub(1, Ordering::AcqRel) == 1 {
+        unsafe { System.dealloc(chunk as *mut u8, chunk_layout()) };
+        G_LIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct Arena;
+
+unsafe impl GlobalAlloc for Arena {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !is_small(&layout) {
+            return unsafe { System.alloc(layout) };
+        }
+        G_CUR.with(|cur| {
+            let (chunk, off) = cur.get();
+            let start = off.next_multiple_of(layout.align());
+            if chunk != 0 && start + layout.size() <= CHUNK {
+                unsafe { refs(chunk) }.fetch_add(1, Ordering::Relaxed);
+                cur.set((chunk, start + layout.size()));
+                return (chunk + start) as *mut u8;
+            }
+            let new = unsafe { System.alloc(chunk_layout()) };
+            if new.is_null() {
+                return new;
+            }
+            G_LIVE.fetch_add(1, Ordering::Relaxed);
+            G_TOTAL.fetch_add(1, Ordering::Relaxed);
+            // one for the thread and one for the block
+            unsafe { (new as *mut AtomicUsize).write(AtomicUsize::new(2)) };
+            if chunk != 0 {
+                unsafe { release(chunk) };
+            }
+            let start = HEAD.next_multiple_of(layout.align());
+            cur.set((new as usize, start + layout.size()));
+            unsafe { new.add(start) }
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !is_small(&layout) {
+            return unsafe { System.dealloc(ptr, layout) };
+        }
+        unsafe { release(ptr as usize & !(CHUNK - 1)) };
+    }
+}
+
+/// chunks of the arena
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaStats {
+    pub chunk_size: usize,
+    /// chunks still held, a single live block keeps a whole chunk
+    pub live: usize,
+    /// chunks taken from the system since the start
+    pub total: usize,
+}
+
+pub fn arena_stats() -> ArenaStats {
+    ArenaStats {
+        chunk_size: CHUNK,
+        live: G_LIVE.load(Ordering::Relaxed),
+        total: G_TOTAL.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arena, CHUNK, MAX_SMALL, refs};
+    use std::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_arena() {
+        // a fresh thread starts without a chunk
+        std::thread::spawn(|| {
+            let mut v = Vec::new();
+            for (i, align) in [1, 8, 16, 64, 4096]
+                .into_iter()
+                .cycle()
+                .take(2000)
+                .enumerate()
+            {
+                let l = Layout::from_size_align(1 + i % 3000, align).unwrap();
+                let p = unsafe { Arena.alloc(l) };
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0);
+                unsafe { p.write_bytes(i as u8, l.size()) };
+                v.push((p, l, i as u8));
+            }
+            let chunk = v.last().unwrap().0 as usize & !(CHUNK - 1);
+            for &(p, l, x) in &v {
+                let s = unsafe { std::slice::from_raw_parts(p, l.size()) };
+                assert!(s.iter().all(|&b| b == x));
+                unsafe { Arena.dealloc(p, l) };
+            }
+            // only the thread holds the current chunk now
+            assert_eq!(
+                unsafe { refs(chunk) }.load(std::sync::atomic::Ordering::Relaxed),
+                1
+            );
+
+            let l = Layout::from_size_align(MAX_SMALL + 1, 8).unwrap();
+            let p = unsafe { Arena.alloc(l) };
+            assert_ne!(p as usize & !(CHUNK - 1), chunk);
+            unsafe { Arena.dealloc(p, l) };
+
+            let l = Layout::from_size_align(100, 8).unwrap();
+            let p = unsafe { Arena.realloc(Arena.alloc(l), l, 5000) };
+            assert!(!p.is_null());
+            unsafe { Arena.dealloc(p, Layout::from_size_align(5000, 8).unwrap()) };
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! counts allocations of any allocator, the counters are sharded by thread so the hot path is a
+//! couple of uncontended atomic adds
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::time::Duration;
+
+const SHARDS: usize = 64;
+
+#[repr(align(64))]
+struct Shard {
+    nr_alloc: AtomicUsize,
+    alloc_size: AtomicUsize,
+    nr_free: AtomicUsize,
+    free_size: AtomicUsize,
+    nr_realloc: AtomicUsize,
+}
+
+impl Shard {
+    const fn new() -> Self {
+        Self {
+            nr_alloc: AtomicUsize::new(0),
+            alloc_size: AtomicUsize::new(0),
+            nr_free: AtomicUsize::new(0),
+            free_size: AtomicUsize::new(0),
+            nr_realloc: AtomicUsize::new(0),
+        }
+    }
+}
+
+static G_SHARDS: [Shard; SHARDS] = [const { Shard::new() }; SHARDS];
+
+thread_local! {
+    static G_SHARD: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// threads take shards round robin on first use
+fn shard() -> &'static Shard {
+    static G_NEXT: AtomicUsize = AtomicUsize::new(0);
+    let i = G_SHARD.with(|x| {
+        if x.get() == usize::MAX {
+            x.set(G_NEXT.fetch_add(1, Relaxed) % SHARDS);
+        }
+        x.get()
+    });
+    &G_SHARDS[i]
+}
+
+/// wraps `A` and counts what goes through it, the counters are shared by every instance
+pub struct Counting<A>(A);
+
+impl<A> Counting<A> {
+    pub const fn new(a: A) -> Self {
+        Self(a)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = unsafe { self.0.alloc(layout) };
+        if !p.is_null() {
+            let s = shard();
+            s.nr_alloc.fetch_add(1, Relaxed);
+            s.alloc_size.fetch_add(layout.size(), Relaxed);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) };
+        let s = shard();
+        s.nr_free.fetch_add(1, Relaxed);
+        s.free_size.fetch_add(layout.size(), Relaxed);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let p = unsafe { self.0.alloc_zeroed(layout) };
+        if !p.is_null() {
+            let s = shard();
+            s.nr_alloc.fetch_add(1, Relaxed);
+            s.alloc_size.fetch_add(layout.size(), Relaxed);
+        }
+        p
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let p = unsafe { self.0.realloc(ptr, layout, new_size) };
+        if !p.is_null() {
+            let s = shard();
+            s.nr_realloc.fetch_add(1, Relaxed);
+            s.alloc_size.fetch_add(new_size, Relaxed);
+            s.free_size.fetch_add(layout.size(), Relaxed);
+        }
+        p
+    }
+}
+
+/// totals of [`Counting`], a realloc counts its new size as allocated and its old one as freed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counts {
+    pub nr_alloc: usize,
+    pub alloc_size: usize,
+    pub nr_free: usize,
+    pub free_size: usize,
+    pub nr_realloc: usize,
+}
+
+pub fn counts() -> Counts {
+    let mut r = Counts::default();
+    for s in &G_SHARDS {
+        r.nr_alloc += s.nr_alloc.load(Relaxed);
+        r.alloc_size += s.alloc_size.load(Relaxed);
+        r.nr_free += s.nr_free.load(Relaxed);
+        r.free_size += s.free_size.load(Relaxed);
+        r.nr_realloc += s.nr_realloc.load(Relaxed);
+    }
+    r
+}
+
+impl Counts {
+    pub fn live(&self) -> usize {
+        self.alloc_size.saturating_sub(self.free_size)
+    }
+
+    /// what happened between `base` and `self`
+    pub fn since(&self, base: &Self) -> Self {
+        Self {
+            nr_alloc: self.nr_alloc - base.nr_alloc,
+            alloc_size: self.alloc_size - base.alloc_size,
+            nr_free: self.nr_free - base.nr_free,
+            free_size: self.free_size - base.free_size,
+            nr_realloc: self.nr_realloc - base.nr_realloc,
+        }
+    }
+
+    /// a summary line with rates over `elapsed`
+    pub fn to_text(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut s = String::new();
+        let _ = write!(
+            s,
+            "alloc {} ({} bytes, {:.0}/s), free {} ({} bytes), realloc {}",
+            self.nr_alloc,
+            self.alloc_size,
+            self.nr_alloc as f64 / secs,
+            self.nr_free,
+            self.free_size,
+            self.nr_realloc
+        );
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Counting, counts};
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    #[test]
+    fn test_counting() {
+        let a = Counting::new(System);
+        let base = counts();
+        let l = Layout::from_size_align(100, 8).unwrap();
+        let p = unsafe { a.alloc(l) };
+        let p = unsafe { a.realloc(p, l, 300) };
+        unsafe { a.dealloc(p, Layout::from_size_align(300, 8).unwrap()) };
+        let z = unsafe { a.alloc_zeroed(l) };
+        unsafe { a.dealloc(z, l) };
+        // other tests may count through their own wrapper concurrently
+        let r = counts().since(&base);
+        assert!(r.nr_alloc >= 2 && r.nr_free >= 2 && r.nr_realloc >= 1);
+        assert!(r.alloc_size >= 500 && r.free_size >= 500);
+        assert!(
+            r.to_text(std::time::Duration::from_secs(1))
+                .starts_with("alloc ")
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use regex_lite::Regex;
+
+/// matched against the source path of a frame
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Substr(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// `re:` followed by a regex, anything else is a substring
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.strip_prefix("re:") {
+            Some(re) => Regex::new(re)
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid regex {re}, {e}")),
+            None => Ok(Self::Substr(s.to_string())),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Substr(x) => path.contains(x.as_str()),
+            Self::Regex(x) => x.is_match(path),
+        }
+    }
+}
+
+/// what identifies a frame in a callsite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    /// `file:line`
+    Line,
+    /// the demangled function name, every line of a function collapses into one frame
+    Function,
+}
+
+impl Group {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "line" => Some(Self::Line),
+            "function" => Some(Self::Function),
+            _ => None,
+        }
+    }
+}
+
+/// decides which frames of a stack make up its callsite, a frame is kept when its path matches
+/// any include pattern, or there's none, and no exclude pattern, stacks without any kept frame
+/// are left out of reports
+#[derive(Debug, Clone)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    group: Group,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter {
+    /// keep every frame which has a source path, grouped by line
+    pub fn new() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            group: Group::Line,
+        }
+    }
+
+    pub fn include(mut self, p: Pattern) -> Self {
+        self.include.push(p);
+        self
+    }
+
+    pub fn exclude(mut self, p: Pattern) -> Self {
+        self.exclude.push(p);
+        self
+    }
+
+    pub fn group(mut self, g: Group) -> Self {
+        self.group = g;
+        self
+    }
+
+    pub fn keep(&self, path: &str) -> bool {
+        !path.is_empty()
+            && (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+
+    /// the frame as it appears in a callsite, `None` if it's filtered out
+    pub fn frame(&self, path: &str, line: u32, function: Option<&str>) -> Option<String> {
+        if !self.keep(path) {
+            return None;
+        }
+        match self.group {
+            Group::Line => Some(format!("{path}:{line}")),
+            Group::Function => Some(function.unwrap_or(path).to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, Group, Pattern};
+
+    #[test]
+    fn test_filter() {
+        let f = Filter::new()
+            .include(Pattern::parse("mace").unwrap())
+            .include(Pattern::parse(r"re:kv_bench/src/\w+\.rs$").unwrap())
+            .exclude(Pattern::parse("mace/src/utils").unwrap());
+        assert!(f.keep("/x/mace/src/map/data.rs"));
+        assert!(f.keep("/root/kv_bench/src/load.rs"));
+        assert!(!f.keep("/root/kv_bench/src/a/load.rs"));
+        assert!(!f.keep("/x/mace/src/utils/mod.rs"));
+        assert!(!f.keep("/rustc/library/alloc/src/vec.rs"));
+        assert!(Pattern::parse("re:(").is_err());
+
+        assert_eq!(
+            f.frame("/x/mace/a.rs", 3, Some("mace::a::f")).as_deref(),
+            Some("/x/mace/a.rs:3")
+        );
+        let f = f.group(Group::Function);
+        assert_eq!(
+            f.frame("/x/mace/a.rs", 3, Some("mace::a::f")).as_deref(),
+            Some("mace::a::f")
+        );
+        assert_eq!(f.frame("/x/other.rs", 3, None), None);
+
+        // no include keeps everything with a path
+        assert!(Filter::new().keep("/any.rs"));
+        assert!(!Filter::new().keep(""));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{G_SELF, G_SLOTS, callsites, nr_slots};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+/// allocations of one callsite still outstanding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leak {
+    pub callsite: String,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// outstanding allocations grouped by callsite, largest first
+#[derive(Debug, Clone, Default)]
+pub struct Leaks {
+    pub leaks: Vec<Leak>,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl Leaks {
+    pub fn exceeds(&self, bytes: usize) -> bool {
+        self.bytes > bytes
+    }
+
+    /// a summary line followed by the first `top` callsites, 0 lists all
+    pub fn to_text(&self, top: usize) -> String {
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "leaked {} allocations, {} bytes, from {} callsites",
+            self.count,
+            self.bytes,
+            self.leaks.len()
+        );
+        let n = if top == 0 { self.leaks.len() } else { top };
+        for x in self.leaks.iter().take(n) {
+            let _ = writeln!(s, "{} allocations, {} bytes", x.count, x.bytes);
+            for l in x.callsite.lines() {
+                let _ = writeln!(s, "    {l}");
+            }
+        }
+        s
+    }
+}
+
+/// outstanding `(count, bytes)` of every slot
+fn outstanding() -> Vec<(usize, usize)> {
+    (0..nr_slots())
+        .map(|i| (G_SLOTS[i].count(), G_SLOTS[i].live()))
+        .collect()
+}
+
+/// allocations outstanding at [`LeakCheck::finish`] which were not at [`LeakCheck::start`], the
+/// comparison is per stack, so freeing older data of a stack hides as many new leaks of it
+pub struct LeakCheck {
+    base: Vec<(usize, usize)>,
+}
+
+impl LeakCheck {
+    /// everything outstanding from now on is a leak
+    pub fn start() -> Self {
+        let old = G_SELF.with(|x| x.replace(true));
+        let r = Self {
+            base: outstanding(),
+        };
+        G_SELF.with(|x| x.set(old));
+        r
+    }
+
+    /// everything outstanding since the process started is a leak
+    pub fn all() -> Self {
+        Self { base: Vec::new() }
+    }
+
+    /// stacks are resolved with the current filter, the ones it drops are ignored
+    pub fn finish(&self) -> Leaks {
+        let old = G_SELF.with(|x| x.replace(true));
+        let r = build(&callsites(), &self.base, &outstanding());
+        G_SELF.with(|x| x.set(old));
+        r
+    }
+}
+
+fn build(keys: &[Option<String>], base: &[(usize, usize)], now: &[(usize, usize)]) -> Leaks {
+    let mut sites: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (id, &(count, bytes)) in now.iter().enumerate() {
+        let Some(Some(key)) = keys.get(id) else {
+            continue;
+        };
+        let (c0, b0) = base.get(id).copied().unwrap_or((0, 0));
+        let (count, bytes) = (count.saturating_sub(c0), bytes.saturating_sub(b0));
+        if count == 0 && bytes == 0 {
+            continue;
+        }
+        let e = sites.entry(key.trim_end()).or_default();
+        e.0 += count;
+        e.1 += bytes;
+    }
+    let mut leaks: Vec<_> = sites
+        .into_iter()
+        .map(|(k, (count, bytes))| Leak {
+            callsite: k.to_string(),
+            count,
+            bytes,
+        })
+        .collect();
+    leaks.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.callsite.cmp(&b.callsite))
+    });
+    Leaks {
+        count: leaks.iter().map(|x| x.count).sum(),
+        bytes: leaks.iter().map(|x| x.bytes).sum(),
+        leaks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build, outstanding};
+    use crate::{MyAlloc, register};
+    use std::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_outstanding() {
+        let site = register(&[200]);
+        let l = Layout::from_size_align(64, 16).unwrap();
+        let kept = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        let base = outstanding();
+        let a = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        let b = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        unsafe { MyAlloc.dealloc(a, l) };
+        let now = outstanding();
+        assert_eq!(base[site], (1, 64));
+        assert_eq!(now[site], (2, 128));
+        unsafe { MyAlloc.dealloc(b, l) };
+        unsafe { MyAlloc.dealloc(kept, l) };
+        assert_eq!(outstanding()[site], (0, 0));
+    }
+
+    #[test]
+    fn test_build() {
+        let keys = vec![
+            None,
+            Some("a\n".into()),
+            Some("b\n".into()),
+            Some("a\n".into()),
+            None,
+        ];
+        let base = [(0, 0), (1, 10), (5, 50), (0, 0)];
+        let now = [(0, 0), (3, 30), (2, 20), (1, 7), (9, 90)];
+        let r = build(&keys, &base, &now);
+        // b freed more than it allocated, slot 4 is filtered out
+        assert_eq!(r.leaks.len(), 1);
+        assert_eq!(r.leaks[0].callsite, "a");
+        assert_eq!((r.count, r.bytes), (3, 27));
+        assert!(r.exceeds(26) && !r.exceeds(27));
+        assert!(
+            r.to_text(0)
+                .starts_with("leaked 3 allocations, 27 bytes, from 1 callsites\n")
+        );
+
+        let r = build(&keys, &[], &now);
+        assert_eq!(r.leaks[1].callsite, "b");
+        assert_eq!((r.count, r.bytes), (6, 57));
+    }
+}
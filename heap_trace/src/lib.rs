@@ -1,71 +1,172 @@
+mod arena;
+mod count;
+mod filter;
+mod leak;
+mod report;
+mod sample;
+mod stats;
+mod timeline;
+
+pub use arena::{Arena, ArenaStats, arena_stats};
+pub use count::{Counting, Counts, counts};
+pub use filter::{Filter, Group, Pattern};
+pub use leak::{Leak, LeakCheck, Leaks};
+pub use report::{Format, Report, Site, SortBy};
+pub use sample::{sample_rate, set_sample_rate};
+pub use stats::{AllocStats, alloc_stats};
+pub use timeline::{Snapshot, Timeline, snapshot, timeline};
+
 use std::{
-    alloc::{GlobalAlloc, System},
+    alloc::{GlobalAlloc, Layout, System},
     cell::Cell,
-    collections::{HashMap, hash_map::Entry},
+    collections::HashMap,
     fmt::Display,
-    hash::{DefaultHasher, Hash, Hasher},
-    ptr,
-    sync::{LazyLock, Mutex, atomic::AtomicBool},
+    sync::{
+        LazyLock, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+    },
 };
 
 pub struct MyAlloc;
 
-fn trace(size: usize, is_alloc: bool) -> Option<String> {
-    let mut key = String::new();
+/// max frames kept of a stack
+const MAX_DEPTH: usize = 64;
+
+/// raw instruction pointers of the current stack, symbolization is left to report time since
+/// it's far too slow for the allocation path
+fn trace(ips: &mut [usize; MAX_DEPTH]) -> usize {
+    let mut n = 0;
     backtrace::trace(|f| {
-        backtrace::resolve_frame(f, |sym| {
-            if let Some(filename) = sym.filename()
-                && let Some(line) = sym.lineno()
-            {
-                if let Some(name) = filename.to_str()
-                    && name.contains("mace")
-                {
-                    if name.len() > 10 {
-                        // sometime name maybe empty
-                        let x = format!("{}:{}\n", name, line);
-                        key.extend(x.chars().into_iter());
-                    }
-                }
-            }
-        });
-        true
+        ips[n] = f.ip() as usize;
+        n += 1;
+        n < MAX_DEPTH
     });
-    if !key.is_empty() {
-        let mut lk = G_MAP.lock().unwrap();
-        let tmp = key.clone();
-        match lk.entry(tmp) {
-            Entry::Vacant(v) => {
-                if is_alloc {
-                    v.insert(Status {
-                        nr_alloc: 1,
-                        alloc_size: size,
-                        nr_free: 0,
-                        free_size: 0,
-                    });
-                } else {
-                    v.insert(Status {
-                        nr_alloc: 0,
-                        alloc_size: 0,
-                        nr_free: 1,
-                        free_size: size,
-                    });
-                }
-            }
-            Entry::Occupied(mut o) => {
-                let s = o.get_mut();
-                if is_alloc {
-                    s.nr_alloc += 1;
-                    s.alloc_size += size;
-                } else {
-                    s.nr_free += 1;
-                    s.free_size += size;
-                }
-            }
+    n
+}
+
+static G_FILTER: LazyLock<RwLock<Filter>> =
+    LazyLock::new(|| RwLock::new(Filter::new().include(Pattern::Substr("mace".into()))));
+
+/// replace the filter applied by reports, the initial one keeps frames whose path contains
+/// `mace`, stacks are kept raw so it applies to allocations made before the change too
+pub fn set_filter(f: Filter) {
+    let old = G_SELF.with(|x| x.replace(true));
+    *G_FILTER.write().unwrap() = f;
+    G_SELF.with(|x| x.set(old));
+}
+
+/// frames of `ip` passing `filter`, one per line, inlined functions make one ip several frames
+fn symbolize(ip: usize, filter: &Filter) -> Vec<String> {
+    let mut r = Vec::new();
+    backtrace::resolve(ip as *mut _, |sym| {
+        let function = sym.name().map(|x| format!("{:#}", x));
+        if let Some(path) = sym.filename().and_then(|x| x.to_str())
+            && let Some(f) = filter.frame(path, sym.lineno().unwrap_or(0), function.as_deref())
+        {
+            r.push(f);
         }
-        Some(key)
-    } else {
-        None
+    });
+    r
+}
+
+/// max number of distinct stacks, allocations from the others are not traced
+const MAX_SITES: usize = 1 << 16;
+
+/// id of allocations which are not traced
+const UNTRACED: usize = 0;
+
+/// counters of a callsite, atomics so frees and reallocs never take a lock
+struct Slot {
+    nr_alloc: AtomicUsize,
+    alloc_size: AtomicUsize,
+    nr_free: AtomicUsize,
+    free_size: AtomicUsize,
+    nr_realloc: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            nr_alloc: AtomicUsize::new(0),
+            alloc_size: AtomicUsize::new(0),
+            nr_free: AtomicUsize::new(0),
+            free_size: AtomicUsize::new(0),
+            nr_realloc: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    fn live(&self) -> usize {
+        // free is loaded first so a concurrent free can't make live look negative
+        let free = self.free_size.load(Relaxed);
+        self.alloc_size.load(Relaxed).saturating_sub(free)
+    }
+
+    /// allocations not freed yet, reallocs keep the count
+    fn count(&self) -> usize {
+        let free = self.nr_free.load(Relaxed);
+        self.nr_alloc.load(Relaxed).saturating_sub(free)
+    }
+
+    fn on_alloc(&self, size: usize) {
+        self.nr_alloc.fetch_add(1, Relaxed);
+        self.alloc_size.fetch_add(size, Relaxed);
+        self.peak.fetch_max(self.live(), Relaxed);
     }
+
+    fn on_free(&self, size: usize) {
+        self.nr_free.fetch_add(1, Relaxed);
+        self.free_size.fetch_add(size, Relaxed);
+    }
+
+    fn on_realloc(&self, old: usize, new: usize) {
+        self.nr_realloc.fetch_add(1, Relaxed);
+        self.alloc_size.fetch_add(new, Relaxed);
+        self.free_size.fetch_add(old, Relaxed);
+        self.peak.fetch_max(self.live(), Relaxed);
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            nr_alloc: self.nr_alloc.load(Relaxed),
+            alloc_size: self.alloc_size.load(Relaxed),
+            nr_free: self.nr_free.load(Relaxed),
+            free_size: self.free_size.load(Relaxed),
+            nr_realloc: self.nr_realloc.load(Relaxed),
+            peak: self.peak.load(Relaxed),
+        }
+    }
+}
+
+static G_SLOTS: [Slot; MAX_SITES] = [const { Slot::new() }; MAX_SITES];
+
+/// stack of each slot, slot 0 is [`UNTRACED`]
+struct Registry {
+    ids: HashMap<Box<[usize]>, usize>,
+    stacks: Vec<Box<[usize]>>,
+}
+
+static G_REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| {
+    Mutex::new(Registry {
+        ids: HashMap::new(),
+        stacks: vec![Box::new([])],
+    })
+});
+
+/// slot id of `stack`, registered on first use
+fn register(stack: &[usize]) -> usize {
+    let mut lk = G_REGISTRY.lock().unwrap();
+    if let Some(&id) = lk.ids.get(stack) {
+        return id;
+    }
+    let id = lk.stacks.len();
+    if id >= MAX_SITES {
+        return UNTRACED;
+    }
+    lk.stacks.push(stack.into());
+    lk.ids.insert(stack.into(), id);
+    id
 }
 
 #[derive(Debug)]
@@ -74,6 +175,25 @@ pub struct Status {
     alloc_size: usize,
     nr_free: usize,
     free_size: usize,
+    nr_realloc: usize,
+    peak: usize,
+}
+
+impl Status {
+    fn live(&self) -> usize {
+        self.alloc_size.saturating_sub(self.free_size)
+    }
+
+    /// stacks which resolve to the same callsite, the peak is the sum of the peaks, which is an
+    /// upper bound of the real one
+    fn merge(&mut self, o: &Status) {
+        self.nr_alloc += o.nr_alloc;
+        self.alloc_size += o.alloc_size;
+        self.nr_free += o.nr_free;
+        self.free_size += o.free_size;
+        self.nr_realloc += o.nr_realloc;
+        self.peak += o.peak;
+    }
 }
 
 impl Display for Status {
@@ -84,141 +204,210 @@ impl Display for Status {
 
 static G_STOP: AtomicBool = AtomicBool::new(false);
 
-static G_MAP: LazyLock<Mutex<HashMap<String, Status>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-
-static G_TRACE: LazyLock<Mutex<HashMap<u64, String>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-
-const META_LEN: usize = 8;
-
 thread_local! {
     static G_SELF: Cell<bool> = const { Cell::new(false) };
 }
 
-const fn real_size(layout: &std::alloc::Layout) -> usize {
-    if META_LEN > layout.align() {
-        META_LEN.checked_add(layout.size()).unwrap()
-    } else {
-        layout.align().checked_add(layout.size()).unwrap()
+/// slot and charged bytes of an allocation of `size`, allocations made while tracing, after
+/// tracing stopped or skipped by sampling are [`UNTRACED`]
+fn sample(size: usize) -> (usize, usize) {
+    if G_SELF.with(|x| x.get()) || G_STOP.load(std::sync::atomic::Ordering::Acquire) {
+        return (UNTRACED, 0);
     }
+    let Some(charged) = sample::pick(size, sample_rate()) else {
+        return (UNTRACED, 0);
+    };
+    G_SELF.with(|x| x.set(true));
+    let mut ips = [0; MAX_DEPTH];
+    let n = trace(&mut ips);
+    let id = register(&ips[..n]);
+    G_SELF.with(|x| x.set(false));
+    (id, charged)
 }
 
-fn new_layout(layout: std::alloc::Layout) -> std::alloc::Layout {
-    let align = layout.align().max(align_of::<u64>());
-    let sz = real_size(&layout);
-    std::alloc::Layout::from_size_align(sz, align).unwrap()
+/// every block starts with padding followed by the header, which sits right before the pointer
+/// handed out so it can be found without knowing where the block starts
+#[repr(C)]
+struct Header {
+    /// slot of the allocating stack
+    site: usize,
+    /// bytes charged to the slot, the size of the block unless it was sampled
+    charged: usize,
+    /// [`stats::now`] at allocation, 0 when untraced
+    born: u64,
 }
 
-fn write_hash(x: *mut u8, align: usize, s: Option<String>) -> *mut u8 {
-    let r = unsafe { x.add(META_LEN.max(align)) };
-    if !G_SELF.with(|x| x.get()) {
-        G_SELF.with(|x| x.set(true));
-        let p = x.cast::<u64>();
-        if let Some(s) = s {
-            let mut stat = DefaultHasher::new();
-            s.hash(&mut stat);
-            let h = stat.finish();
-            unsafe { p.write_unaligned(h) };
-            let mut lk = G_TRACE.lock().unwrap();
-            lk.insert(h, s);
-        } else {
-            unsafe { p.write_unaligned(u64::MAX) };
-        }
-        G_SELF.with(|x| x.set(false));
-    }
-    r
+const HEADER_LEN: usize = size_of::<Header>();
+
+/// distance from the start of the block to the pointer handed out, a multiple of the alignment
+/// so the pointer stays aligned
+const fn offset(align: usize) -> usize {
+    HEADER_LEN.next_multiple_of(align)
 }
 
-fn read_hash(x: *mut u8, align: usize) -> *mut u8 {
-    let (h, p) = unsafe {
-        let p = x.sub(META_LEN.max(align)).cast::<u64>();
-        (p.read_unaligned(), p.cast::<u8>())
-    };
+/// layout of the whole block holding `size` bytes aligned to `align`
+fn block_layout(size: usize, align: usize) -> Layout {
+    let sz = offset(align).checked_add(size).unwrap();
+    Layout::from_size_align(sz, align.max(align_of::<Header>())).unwrap()
+}
 
-    if h == u64::MAX {
-        return p;
+/// # Safety
+/// `raw` is the start of a block of [`block_layout`] for `align`
+unsafe fn write_header(raw: *mut u8, align: usize, h: Header) -> *mut u8 {
+    unsafe {
+        let p = raw.add(offset(align));
+        p.cast::<Header>().sub(1).write(h);
+        p
     }
-    if !G_SELF.with(|x| x.get()) {
-        G_SELF.with(|x| x.set(true));
-        let mut lk = G_TRACE.lock().unwrap();
-        lk.remove(&h);
-        G_SELF.with(|x| x.set(false));
+}
+
+/// # Safety
+/// `ptr` was returned by [`MyAlloc`] with alignment `align`
+unsafe fn read_header(ptr: *mut u8, align: usize) -> (*mut u8, Header) {
+    unsafe {
+        let h = ptr.cast::<Header>().sub(1).read();
+        (ptr.sub(offset(align)), h)
     }
-    p
 }
 
-unsafe impl GlobalAlloc for MyAlloc {
-    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
-        let s = if !G_SELF.with(|x| x.get()) && !G_STOP.load(std::sync::atomic::Ordering::Acquire) {
-            G_SELF.with(|x| x.set(true));
-            let x = trace(layout.size(), true);
-            G_SELF.with(|x| x.set(false));
-            x
-        } else {
-            None
+impl MyAlloc {
+    unsafe fn alloc_site(
+        &self,
+        layout: Layout,
+        (site, charged): (usize, usize),
+        zeroed: bool,
+    ) -> *mut u8 {
+        let block = block_layout(layout.size(), layout.align());
+        let raw = unsafe {
+            if zeroed {
+                System.alloc_zeroed(block)
+            } else {
+                System.alloc(block)
+            }
         };
+        if raw.is_null() {
+            return raw;
+        }
+        let mut born = 0;
+        if site != UNTRACED {
+            G_SLOTS[site].on_alloc(charged);
+            stats::on_alloc(layout.size(), charged);
+            born = stats::now();
+        }
+        let h = Header {
+            site,
+            charged,
+            born,
+        };
+        unsafe { write_header(raw, layout.align(), h) }
+    }
+}
 
-        let new = new_layout(layout);
-        let x = unsafe { System.alloc(new) };
-        write_hash(x, new.align(), s)
+unsafe impl GlobalAlloc for MyAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.alloc_site(layout, sample(layout.size()), false) }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
-        if !G_SELF.with(|x| x.get()) && !G_STOP.load(std::sync::atomic::Ordering::Acquire) {
-            G_SELF.with(|x| x.set(true));
-            trace(layout.size(), false);
-            G_SELF.with(|x| x.set(false));
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (raw, h) = unsafe { read_header(ptr, layout.align()) };
+        if h.site != UNTRACED {
+            G_SLOTS[h.site].on_free(h.charged);
+            stats::on_free(layout.size(), h.charged, h.born);
         }
-        let new = new_layout(layout);
-        let p = read_hash(ptr, new.align());
-        unsafe { System.dealloc(p, new) };
+        unsafe { System.dealloc(raw, block_layout(layout.size(), layout.align())) };
     }
 
-    unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
-        let p = unsafe { self.alloc(layout) };
-        if !p.is_null() {
-            unsafe { ptr::write_bytes(p, 0, layout.size()) };
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.alloc_site(layout, sample(layout.size()), true) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let align = layout.align();
+        let (raw, h) = unsafe { read_header(ptr, align) };
+        let new = block_layout(new_size, align);
+        let new_raw =
+            unsafe { System.realloc(raw, block_layout(layout.size(), align), new.size()) };
+        if new_raw.is_null() {
+            return new_raw;
         }
-        p
+        // a sampled block keeps standing for the same multiple of its size
+        let charged =
+            (h.charged as u128 * new_size as u128 / layout.size().max(1) as u128) as usize;
+        if h.site != UNTRACED {
+            G_SLOTS[h.site].on_realloc(h.charged, charged);
+            // a free of the old size and an allocation of the new one for the size classes
+            stats::on_free(layout.size(), h.charged, h.born);
+            stats::on_alloc(new_size, charged);
+        }
+        unsafe { write_header(new_raw, align, Header { charged, ..h }) }
     }
+}
 
-    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
-        let s = if !G_SELF.with(|x| x.get()) && !G_STOP.load(std::sync::atomic::Ordering::Acquire) {
-            G_SELF.with(|x| x.set(true));
-            let x = trace(layout.size(), true);
-            G_SELF.with(|x| x.set(false));
-            x
-        } else {
-            None
-        };
+/// number of registered slots including [`UNTRACED`]
+fn nr_slots() -> usize {
+    G_REGISTRY.lock().unwrap().stacks.len()
+}
 
-        unsafe {
-            let old_layout = new_layout(layout);
-            let raw = ptr.sub(META_LEN.max(old_layout.align()));
-            let new_total_size = META_LEN + new_size;
+/// callsite of each slot, stacks are symbolized here, `None` when no frame passes the filter,
+/// the caller must hold [`G_SELF`]
+fn callsites() -> Vec<Option<String>> {
+    let stacks: Vec<_> = G_REGISTRY.lock().unwrap().stacks.clone();
+    let filter = G_FILTER.read().unwrap();
+    let mut frames: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut r = vec![None];
+    for stack in stacks.iter().skip(1) {
+        let mut key = String::new();
+        let mut last = 0;
+        for &ip in stack.iter() {
+            for f in frames
+                .entry(ip)
+                .or_insert_with(|| symbolize(ip, &filter))
+                .iter()
+            {
+                // recursion or several lines of one function grouped by name
+                if key[last..].trim_end() == f {
+                    continue;
+                }
+                last = key.len();
+                key.push_str(f);
+                key.push('\n');
+            }
+        }
+        r.push((!key.is_empty()).then_some(key));
+    }
+    r
+}
 
-            let new_raw = System.realloc(raw, old_layout, new_total_size);
-            if new_raw.is_null() {
-                return new_raw;
+/// callsites with their status, stacks resolving to the same callsite are merged and the ones
+/// filtered out dropped, allocations made by the caller while collecting are not traced
+fn collect() -> Vec<(String, Status)> {
+    let old = G_SELF.with(|x| x.replace(true));
+    let mut sites: HashMap<String, Status> = HashMap::new();
+    for (id, key) in callsites().into_iter().enumerate() {
+        let Some(key) = key else {
+            continue;
+        };
+        let s = G_SLOTS[id].status();
+        match sites.get_mut(&key) {
+            Some(x) => x.merge(&s),
+            None => {
+                sites.insert(key, s);
             }
-            write_hash(new_raw, old_layout.align(), s)
         }
     }
+    let r = sites.into_iter().collect();
+    G_SELF.with(|x| x.set(old));
+    r
 }
 
+/// callsites which still have live allocations
 pub fn print_filtered_trace<F>(f: F)
 where
     F: Fn(&str, &Status),
 {
     G_STOP.store(true, std::sync::atomic::Ordering::Release);
-    let lk = G_MAP.lock().unwrap();
-    let t = G_TRACE.lock().unwrap();
-
-    for (_, v) in t.iter() {
-        if let Some(s) = lk.get(v) {
-            f(v, s);
-        }
+    for (k, v) in collect().iter().filter(|(_, v)| v.live() > 0) {
+        f(k, v);
     }
 }
 
@@ -227,7 +416,150 @@ where
     F: Fn(&str, &Status),
 {
     G_STOP.store(true, std::sync::atomic::Ordering::Release);
-    let lk = G_MAP.lock().unwrap();
+    collect().iter().for_each(|(k, v)| f(k, v));
+}
+
+/// accounting of every callsite, allocations made by the caller while collecting are not traced
+pub fn sites() -> Vec<Site> {
+    let sites = collect();
+    let old = G_SELF.with(|x| x.replace(true));
+    let r = sites
+        .into_iter()
+        .map(|(k, v)| Site {
+            callsite: k.trim_end().to_string(),
+            nr_alloc: v.nr_alloc,
+            alloc_size: v.alloc_size,
+            nr_free: v.nr_free,
+            free_size: v.free_size,
+            nr_realloc: v.nr_realloc,
+            live: v.live(),
+            peak: v.peak,
+        })
+        .collect();
+    G_SELF.with(|x| x.set(old));
+    r
+}
+
+/// the `top` callsites ordered by `sort`, 0 keeps all, tracing goes on afterwards
+pub fn report(sort: SortBy, top: usize) -> Report {
+    let sites = sites();
+    let old = G_SELF.with(|x| x.replace(true));
+    let r = Report::new(sites, sort, top);
+    G_SELF.with(|x| x.set(old));
+    r
+}
 
-    lk.iter().for_each(|(k, v)| f(k, v));
+#[cfg(test)]
+mod test {
+    use super::{G_SLOTS, MyAlloc, Status, register};
+    use std::alloc::{GlobalAlloc, Layout};
+
+    // the allocator is exercised directly with an explicit slot and charge, each test registers
+    // its own fake stack so they can run concurrently
+
+    const ALIGNS: [usize; 6] = [1, 8, 16, 32, 64, 4096];
+
+    fn status(site: usize) -> Status {
+        G_SLOTS[site].status()
+    }
+
+    #[test]
+    fn test_alloc_free() {
+        let site = register(&[1]);
+        let mut total = 0;
+        for align in ALIGNS {
+            for size in [1, 7, 24, 100, 5000] {
+                let l = Layout::from_size_align(size, align).unwrap();
+                let p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0);
+                unsafe { p.write_bytes(0xab, size) };
+                total += size;
+                assert_eq!(status(site).live(), size);
+                unsafe { MyAlloc.dealloc(p, l) };
+                assert_eq!(status(site).live(), 0);
+            }
+        }
+        let s = status(site);
+        assert_eq!((s.nr_alloc, s.nr_free), (30, 30));
+        assert_eq!((s.alloc_size, s.free_size), (total, total));
+        assert_eq!(s.peak, 5000);
+    }
+
+    #[test]
+    fn test_alloc_zeroed() {
+        let site = register(&[2]);
+        for align in ALIGNS {
+            let l = Layout::from_size_align(300, align).unwrap();
+            let p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), true) };
+            assert_eq!(p as usize % align, 0);
+            let s = unsafe { std::slice::from_raw_parts(p, l.size()) };
+            assert!(s.iter().all(|&x| x == 0));
+            unsafe { MyAlloc.dealloc(p, l) };
+        }
+        let s = status(site);
+        assert_eq!((s.nr_alloc, s.nr_free, s.live()), (6, 6, 0));
+        assert_eq!(s.alloc_size, 6 * 300);
+    }
+
+    #[test]
+    fn test_realloc() {
+        let site = register(&[3]);
+        for align in ALIGNS {
+            let l = Layout::from_size_align(16, align).unwrap();
+            let mut p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+            for i in 0..16 {
+                unsafe { p.add(i).write(i as u8) };
+            }
+            let mut size = l.size();
+            // grow then shrink, the content and the alignment must survive each move
+            for new_size in [64, 10000, 8] {
+                p = unsafe {
+                    MyAlloc.realloc(p, Layout::from_size_align(size, align).unwrap(), new_size)
+                };
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0);
+                for i in 0..8 {
+                    assert_eq!(unsafe { p.add(i).read() }, i as u8);
+                }
+                size = new_size;
+                assert_eq!(status(site).live(), size);
+            }
+            unsafe { MyAlloc.dealloc(p, Layout::from_size_align(size, align).unwrap()) };
+            assert_eq!(status(site).live(), 0);
+        }
+        let s = status(site);
+        assert_eq!((s.nr_alloc, s.nr_realloc, s.nr_free), (6, 18, 6));
+        assert_eq!(s.peak, 10000);
+    }
+
+    #[test]
+    fn test_untraced() {
+        // frees and reallocs of untraced blocks must not be charged anywhere
+        let site = register(&[4]);
+        let l = Layout::from_size_align(100, 32).unwrap();
+        let p = unsafe { MyAlloc.alloc_site(l, (super::UNTRACED, 0), false) };
+        let p = unsafe { MyAlloc.realloc(p, l, 200) };
+        unsafe { MyAlloc.dealloc(p, Layout::from_size_align(200, 32).unwrap()) };
+        let s = status(site);
+        assert_eq!((s.nr_alloc, s.nr_free, s.alloc_size), (0, 0, 0));
+        assert_eq!(register(&[4]), site);
+    }
+
+    #[test]
+    fn test_sampled() {
+        // a sampled block stands for more than its size, reallocs keep the ratio
+        let site = register(&[5]);
+        let l = Layout::from_size_align(100, 8).unwrap();
+        let p = unsafe { MyAlloc.alloc_site(l, (site, 4000), false) };
+        assert_eq!(status(site).live(), 4000);
+        let p = unsafe { MyAlloc.realloc(p, l, 200) };
+        assert_eq!(status(site).live(), 8000);
+        unsafe { MyAlloc.dealloc(p, Layout::from_size_align(200, 8).unwrap()) };
+        let s = status(site);
+        assert_eq!(
+            (s.live(), s.peak, s.nr_alloc, s.nr_realloc),
+            (0, 8000, 1, 1)
+        );
+    }
 }
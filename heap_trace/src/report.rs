@@ -0,0 +1,220 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// accounting of one callsite, a callsite is the filtered stack of an allocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    pub callsite: String,
+    pub nr_alloc: usize,
+    pub alloc_size: usize,
+    pub nr_free: usize,
+    pub free_size: usize,
+    /// reallocations of blocks allocated here, each adds the new size to `alloc_size` and the
+    /// old one to `free_size`
+    pub nr_realloc: usize,
+    /// bytes allocated and not yet freed
+    pub live: usize,
+    /// highest `live` ever seen
+    pub peak: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Live,
+    Peak,
+    AllocSize,
+    AllocCount,
+}
+
+impl SortBy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "live" => Some(Self::Live),
+            "peak" => Some(Self::Peak),
+            "alloc" => Some(Self::AllocSize),
+            "count" => Some(Self::AllocCount),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// callsites sorted in descending order and truncated, totals cover every callsite
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub sites: Vec<Site>,
+    /// number of callsites before truncation
+    pub nr_sites: usize,
+    pub total_alloc: usize,
+    pub total_free: usize,
+    pub total_live: usize,
+}
+
+impl Report {
+    /// build from every callsite, keeping the first `top` ones ordered by `sort`, 0 keeps all
+    pub fn new(mut sites: Vec<Site>, sort: SortBy, top: usize) -> Self {
+        let mut r = Self {
+            nr_sites: sites.len(),
+            ..Default::default()
+        };
+        for s in &sites {
+            r.total_alloc += s.alloc_size;
+            r.total_free += s.free_size;
+            r.total_live += s.live;
+        }
+        sites.sort_by(|a, b| {
+            let key = |s: &Site| match sort {
+                SortBy::Live => (s.live, s.peak),
+                SortBy::Peak => (s.peak, s.live),
+                SortBy::AllocSize => (s.alloc_size, s.nr_alloc),
+                SortBy::AllocCount => (s.nr_alloc, s.alloc_size),
+            };
+            key(b)
+                .cmp(&key(a))
+                .then_with(|| a.callsite.cmp(&b.callsite))
+        });
+        if top > 0 {
+            sites.truncate(top);
+        }
+        r.sites = sites;
+        r
+    }
+
+    /// a table, each row is followed by its callsite indented
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "callsites {} total_alloc {} total_free {} total_live {}",
+            self.nr_sites, self.total_alloc, self.total_free, self.total_live
+        );
+        let _ = writeln!(
+            s,
+            "{:>14} {:>14} {:>10} {:>14} {:>10} {:>14} {:>10}",
+            "live", "peak", "nr_alloc", "alloc_size", "nr_free", "free_size", "nr_realloc"
+        );
+        for x in &self.sites {
+            let _ = writeln!(
+                s,
+                "{:>14} {:>14} {:>10} {:>14} {:>10} {:>14} {:>10}",
+                x.live, x.peak, x.nr_alloc, x.alloc_size, x.nr_free, x.free_size, x.nr_realloc
+            );
+            for l in x.callsite.lines() {
+                let _ = writeln!(s, "    {l}");
+            }
+        }
+        s
+    }
+
+    /// a single json object, the callsite is an array of frames
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        let _ = write!(
+            s,
+            "{{\"callsites\":{},\"total_alloc\":{},\"total_free\":{},\"total_live\":{},\"sites\":[",
+            self.nr_sites, self.total_alloc, self.total_free, self.total_live
+        );
+        for (i, x) in self.sites.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            let _ = write!(
+                s,
+                "{{\"live\":{},\"peak\":{},\"nr_alloc\":{},\"alloc_size\":{},\"nr_free\":{},\"free_size\":{},\"nr_realloc\":{},\"frames\":[",
+                x.live, x.peak, x.nr_alloc, x.alloc_size, x.nr_free, x.free_size, x.nr_realloc
+            );
+            for (j, l) in x.callsite.lines().enumerate() {
+                if j > 0 {
+                    s.push(',');
+                }
+                json_str(&mut s, l);
+            }
+            s.push_str("]}");
+        }
+        s.push_str("]}\n");
+        s
+    }
+
+    pub fn format(&self, fmt: Format) -> String {
+        match fmt {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json(),
+        }
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>, fmt: Format) -> std::io::Result<()> {
+        std::fs::write(path, self.format(fmt))
+    }
+}
+
+fn json_str(s: &mut String, x: &str) {
+    s.push('"');
+    for c in x.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(s, "\\u{:04x}", c as u32);
+            }
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Report, Site, SortBy};
+
+    fn site(name: &str, alloc: usize, free: usize, peak: usize) -> Site {
+        Site {
+            callsite: name.into(),
+            nr_alloc: 1,
+            alloc_size: alloc,
+            nr_free: 1,
+            free_size: free,
+            nr_realloc: 0,
+            live: alloc - free,
+            peak,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let sites = vec![
+            site("a.rs:1\nb.rs:2", 100, 90, 100),
+            site("c.rs:3", 50, 0, 50),
+            site("d\"e.rs:4", 200, 200, 120),
+        ];
+        let r = Report::new(sites.clone(), SortBy::Live, 2);
+        assert_eq!(r.nr_sites, 3);
+        assert_eq!((r.total_alloc, r.total_free, r.total_live), (350, 290, 60));
+        let names: Vec<_> = r.sites.iter().map(|x| x.callsite.as_str()).collect();
+        assert_eq!(names, ["c.rs:3", "a.rs:1\nb.rs:2"]);
+
+        let r = Report::new(sites, SortBy::Peak, 0);
+        assert_eq!(r.sites.len(), 3);
+        assert_eq!(r.sites[0].callsite, "d\"e.rs:4");
+        let json = r.to_json();
+        assert!(json.contains(r#""frames":["d\"e.rs:4"]"#));
+        assert!(json.contains(r#""frames":["a.rs:1","b.rs:2"]"#));
+        assert!(r.to_text().contains("    b.rs:2\n"));
+    }
+}
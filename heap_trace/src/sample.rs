@@ -0,0 +1,115 @@
+//! poisson sampling of allocated bytes, each thread counts down an exponentially distributed
+//! number of bytes and the allocation crossing zero is sampled, so an allocation of `s` bytes is
+//! sampled with probability `1 - exp(-s / rate)` and charged `s` divided by that probability,
+//! which keeps the totals unbiased
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+static G_RATE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// bytes left before the next sample, 0 until the first draw
+    static G_LEFT: Cell<usize> = const { Cell::new(0) };
+    static G_RNG: Cell<u64> = const { Cell::new(0) };
+}
+
+/// sample on average once every `bytes` allocated bytes, 0 traces every allocation, threads
+/// pick up a new rate after their current countdown runs out
+pub fn set_sample_rate(bytes: usize) {
+    G_RATE.store(bytes, Relaxed);
+}
+
+pub fn sample_rate() -> usize {
+    G_RATE.load(Relaxed)
+}
+
+fn next_u64() -> u64 {
+    static G_SEED: AtomicUsize = AtomicUsize::new(0);
+    G_RNG.with(|x| {
+        let mut s = x.get();
+        if s == 0 {
+            // distinct per thread, the address of the thread local tells threads apart
+            s = (x as *const _ as u64) ^ (G_SEED.fetch_add(1, Relaxed) as u64).rotate_left(32);
+        }
+        s = s.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        x.set(s);
+        let mut z = s;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// exponentially distributed with mean `rate`, at least 1
+fn draw(rate: usize) -> usize {
+    // uniform in (0, 1]
+    let u = ((next_u64() >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    (-u.ln() * rate as f64) as usize + 1
+}
+
+/// bytes a sampled allocation of `size` stands for
+pub(crate) fn weight(size: usize, rate: usize) -> usize {
+    let p = 1.0 - (-(size as f64) / rate as f64).exp();
+    (size as f64 / p) as usize
+}
+
+/// bytes to charge if an allocation of `size` is sampled, every allocation is when `rate` is 0
+pub(crate) fn pick(size: usize, rate: usize) -> Option<usize> {
+    if rate == 0 {
+        return Some(size);
+    }
+    G_LEFT.with(|x| {
+        let left = match x.get() {
+            0 => draw(rate),
+            n => n,
+        };
+        if size < left {
+            x.set(left - size);
+            None
+        } else {
+            x.set(draw(rate));
+            Some(weight(size, rate))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{draw, pick, weight};
+
+    #[test]
+    fn test_weight() {
+        // tiny allocations stand for about `rate` bytes, huge ones for themselves
+        assert!((weight(1, 4096) as i64 - 4096).abs() <= 1);
+        assert_eq!(weight(1 << 30, 4096), 1 << 30);
+        assert_eq!(pick(100, 0), Some(100));
+    }
+
+    #[test]
+    fn test_unbiased() {
+        // fresh thread so the countdown starts from scratch
+        std::thread::spawn(|| {
+            let rate = 256 << 10;
+            let n = 200_000;
+            let mean = (0..n).map(|_| draw(rate)).sum::<usize>() / n;
+            assert!(mean.abs_diff(rate) < rate / 20, "mean {mean}");
+
+            let (mut total, mut charged, mut samples) = (0, 0, 0);
+            for i in 0..n {
+                // mix of small and large sizes
+                let size = [16, 100, 1000, 100_000][i % 4];
+                total += size;
+                if let Some(x) = pick(size, rate) {
+                    charged += x;
+                    samples += 1;
+                }
+            }
+            // about 1 in 12 of this mix is sampled
+            assert!(samples < n / 5, "{samples} samples");
+            assert!(charged.abs_diff(total) < total / 20, "{charged} vs {total}");
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! what is allocated rather than where, size classes, allocation rate and lifetimes of traced
+//! allocations, a sampled allocation counts as the number of allocations it stands for so the
+//! figures are estimates when sampling
+
+use std::fmt::Write as _;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+/// class `i` holds sizes in `(2^(i-1), 2^i]`, class 0 sizes 0 and 1
+const NR_CLASSES: usize = usize::BITS as usize + 1;
+
+/// upper bounds of the lifetime buckets in nanoseconds, the last bucket has none
+const LIFETIMES: [u64; 8] = [
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+];
+
+const NR_LIFETIMES: usize = LIFETIMES.len() + 1;
+
+/// allocations freed in less than a millisecond are short-lived
+const SHORT_LIVED: usize = 4;
+
+static G_COUNT: [AtomicUsize; NR_CLASSES] = [const { AtomicUsize::new(0) }; NR_CLASSES];
+static G_BYTES: [AtomicUsize; NR_CLASSES] = [const { AtomicUsize::new(0) }; NR_CLASSES];
+static G_LIFETIME: [AtomicUsize; NR_LIFETIMES] = [const { AtomicUsize::new(0) }; NR_LIFETIMES];
+static G_EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+fn class(size: usize) -> usize {
+    (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize
+}
+
+fn lifetime(nanos: u64) -> usize {
+    LIFETIMES.partition_point(|&x| x <= nanos)
+}
+
+/// allocations a block of `size` charged `charged` bytes stands for
+fn weight(size: usize, charged: usize) -> usize {
+    (charged / size.max(1)).max(1)
+}
+
+/// nanoseconds since the first call, the birth time kept in headers
+pub(crate) fn now() -> u64 {
+    G_EPOCH.elapsed().as_nanos() as u64
+}
+
+pub(crate) fn on_alloc(size: usize, charged: usize) {
+    let c = class(size);
+    G_COUNT[c].fetch_add(weight(size, charged), Relaxed);
+    G_BYTES[c].fetch_add(charged, Relaxed);
+}
+
+/// a realloc counts as a free of the old size too, the block keeps its birth time so its final
+/// free spans its whole life
+pub(crate) fn on_free(size: usize, charged: usize, born: u64) {
+    let l = lifetime(now().saturating_sub(born));
+    G_LIFETIME[l].fetch_add(weight(size, charged), Relaxed);
+}
+
+/// counters of traced allocations, either since the process started or, through
+/// [`AllocStats::since`], over a period
+#[derive(Debug, Clone)]
+pub struct AllocStats {
+    pub elapsed: Duration,
+    /// allocations by size class, see [`AllocStats::class_bound`]
+    pub count: [usize; NR_CLASSES],
+    /// bytes allocated by size class
+    pub bytes: [usize; NR_CLASSES],
+    /// frees by lifetime bucket, see [`AllocStats::lifetime_bound`]
+    pub lifetimes: [usize; NR_LIFETIMES],
+}
+
+pub fn alloc_stats() -> AllocStats {
+    let load = |x: &AtomicUsize| x.load(Relaxed);
+    AllocStats {
+        elapsed: G_EPOCH.elapsed(),
+        count: G_COUNT.each_ref().map(load),
+        bytes: G_BYTES.each_ref().map(load),
+        lifetimes: G_LIFETIME.each_ref().map(load),
+    }
+}
+
+impl AllocStats {
+    /// largest size of class `i`
+    pub fn class_bound(i: usize) -> u128 {
+        1 << i
+    }
+
+    /// lifetimes of bucket `i` are below the bound, `None` for the last one
+    pub fn lifetime_bound(i: usize) -> Option<Duration> {
+        LIFETIMES.get(i).map(|&x| Duration::from_nanos(x))
+    }
+
+    /// what happened between `base` and `self`
+    pub fn since(&self, base: &Self) -> Self {
+        let sub = |a: &[usize], b: &[usize], r: &mut [usize]| {
+            for (i, x) in r.iter_mut().enumerate() {
+                *x = a[i].saturating_sub(b[i]);
+            }
+        };
+        let mut r = self.clone();
+        r.elapsed = self.elapsed.saturating_sub(base.elapsed);
+        sub(&self.count, &base.count, &mut r.count);
+        sub(&self.bytes, &base.bytes, &mut r.bytes);
+        sub(&self.lifetimes, &base.lifetimes, &mut r.lifetimes);
+        r
+    }
+
+    pub fn nr_alloc(&self) -> usize {
+        self.count.iter().sum()
+    }
+
+    pub fn alloc_size(&self) -> usize {
+        self.bytes.iter().sum()
+    }
+
+    pub fn nr_free(&self) -> usize {
+        self.lifetimes.iter().sum()
+    }
+
+    /// allocations and bytes per second
+    pub fn rate(&self) -> (f64, f64) {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (
+                self.nr_alloc() as f64 / secs,
+                self.alloc_size() as f64 / secs,
+            )
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// share of the frees which happened within a millisecond of the allocation
+    pub fn short_lived(&self) -> f64 {
+        let n = self.nr_free();
+        if n == 0 {
+            return 0.0;
+        }
+        self.lifetimes[..SHORT_LIVED].iter().sum::<usize>() as f64 / n as f64
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        let (nr, bytes) = self.rate();
+        let _ = writeln!(
+            s,
+            "allocated {} blocks, {} bytes in {:.3}s, {:.0} allocs/s, {:.0} bytes/s",
+            self.nr_alloc(),
+            self.alloc_size(),
+            self.elapsed.as_secs_f64(),
+            nr,
+            bytes
+        );
+        let _ = writeln!(s, "{:>12} {:>12} {:>16}", "size <=", "count", "bytes");
+        for i in 0..NR_CLASSES {
+            if self.count[i] > 0 {
+                let _ = writeln!(
+                    s,
+                    "{:>12} {:>12} {:>16}",
+                    Self::class_bound(i),
+                    self.count[i],
+                    self.bytes[i]
+                );
+            }
+        }
+        let _ = writeln!(s, "{:>12} {:>12}", "lifetime <", "count");
+        for (i, x) in self.lifetimes.iter().enumerate() {
+            let bound = match Self::lifetime_bound(i) {
+                Some(x) => format!("{:?}", x),
+                None => "inf".to_string(),
+            };
+            let _ = writeln!(s, "{:>12} {:>12}", bound, x);
+        }
+        let _ = writeln!(
+            s,
+            "short-lived {:.1}% of {} freed, {} not freed",
+            self.short_lived() * 100.0,
+            self.nr_free(),
+            self.nr_alloc().saturating_sub(self.nr_free())
+        );
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AllocStats, alloc_stats, class, lifetime, weight};
+    use crate::{MyAlloc, register};
+    use std::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_buckets() {
+        assert_eq!(
+            [class(0), class(1), class(2), class(3), class(4)],
+            [0, 0, 1, 2, 2]
+        );
+        assert_eq!(class(4097), 13);
+        assert_eq!(AllocStats::class_bound(class(4097)), 8192);
+        assert_eq!(class(usize::MAX), 64);
+        assert_eq!([lifetime(0), lifetime(999), lifetime(1000)], [0, 0, 1]);
+        assert_eq!(lifetime(u64::MAX), 8);
+        assert_eq!(weight(100, 100), 1);
+        assert_eq!(weight(100, 4000), 40);
+    }
+
+    #[test]
+    fn test_stats() {
+        let site = register(&[300]);
+        let base = alloc_stats();
+        let l = Layout::from_size_align(3000, 8).unwrap();
+        let p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        let q = unsafe { MyAlloc.alloc_site(l, (site, 30000), false) };
+        unsafe { MyAlloc.dealloc(p, l) };
+        unsafe { MyAlloc.dealloc(q, l) };
+        // other tests allocate concurrently, only lower bounds hold
+        let r = alloc_stats().since(&base);
+        assert!(r.count[class(3000)] >= 11);
+        assert!(r.bytes[class(3000)] >= 33000);
+        assert!(r.nr_free() >= 11);
+        assert!(r.short_lived() > 0.0);
+        assert!(r.to_text().starts_with("allocated "));
+    }
+
+    #[test]
+    fn test_realloc_stats() {
+        let site = register(&[301]);
+        let base = alloc_stats();
+        let l = Layout::from_size_align(3000, 8).unwrap();
+        let new_size = 3 << 20;
+        let p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        let p = unsafe { MyAlloc.realloc(p, l, new_size) };
+        unsafe { MyAlloc.dealloc(p, Layout::from_size_align(new_size, 8).unwrap()) };
+        let r = alloc_stats().since(&base);
+        assert!(r.count[class(3000)] >= 1);
+        assert!(r.count[class(new_size)] >= 1);
+        assert!(r.bytes[class(new_size)] >= new_size);
+        // the realloc and the final free
+        assert!(r.nr_free() >= 2);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{G_SELF, G_SLOTS, callsites, nr_slots};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// live bytes of every traced stack at a point in time, taking one is a pass over atomics, the
+/// stacks are only symbolized by [`timeline`]
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub at: Instant,
+    /// live bytes of every traced stack, including the ones the filter drops
+    pub total_live: usize,
+    /// indexed by slot
+    live: Vec<usize>,
+}
+
+pub fn snapshot() -> Snapshot {
+    let old = G_SELF.with(|x| x.replace(true));
+    let live: Vec<usize> = (0..nr_slots()).map(|i| G_SLOTS[i].live()).collect();
+    let r = Snapshot {
+        at: Instant::now(),
+        total_live: live.iter().sum(),
+        live,
+    };
+    G_SELF.with(|x| x.set(old));
+    r
+}
+
+/// per-callsite live bytes over a series of snapshots
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub at: Vec<Instant>,
+    pub total_live: Vec<usize>,
+    /// callsites ordered by their highest live bytes over the series
+    pub sites: Vec<String>,
+    /// `live[i][j]` is the live bytes of `sites[j]` at `at[i]`
+    pub live: Vec<Vec<usize>>,
+}
+
+/// resolve the stacks of `snaps` with the current filter and keep the `top` callsites with the
+/// highest live bytes at any point, 0 keeps all
+pub fn timeline(snaps: &[Snapshot], top: usize) -> Timeline {
+    let old = G_SELF.with(|x| x.replace(true));
+    let r = build(&callsites(), snaps, top);
+    G_SELF.with(|x| x.set(old));
+    r
+}
+
+/// `keys` is the callsite of each slot
+fn build(keys: &[Option<String>], snaps: &[Snapshot], top: usize) -> Timeline {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut names = Vec::new();
+    let site: Vec<Option<usize>> = keys
+        .iter()
+        .map(|k| {
+            let k = k.as_deref()?.trim_end();
+            Some(*index.entry(k).or_insert_with(|| {
+                names.push(k.to_string());
+                names.len() - 1
+            }))
+        })
+        .collect();
+
+    let rows: Vec<Vec<usize>> = snaps
+        .iter()
+        .map(|s| {
+            let mut row = vec![0; names.len()];
+            for (id, &live) in s.live.iter().enumerate() {
+                if let Some(Some(j)) = site.get(id) {
+                    row[*j] += live;
+                }
+            }
+            row
+        })
+        .collect();
+
+    let max: Vec<usize> = (0..names.len())
+        .map(|j| rows.iter().map(|r| r[j]).max().unwrap_or(0))
+        .collect();
+    let mut order: Vec<usize> = (0..names.len()).collect();
+    order.sort_by(|&a, &b| max[b].cmp(&max[a]).then_with(|| names[a].cmp(&names[b])));
+    if top > 0 {
+        order.truncate(top);
+    }
+
+    Timeline {
+        at: snaps.iter().map(|s| s.at).collect(),
+        total_live: snaps.iter().map(|s| s.total_live).collect(),
+        sites: order.iter().map(|&j| names[j].clone()).collect(),
+        live: rows
+            .iter()
+            .map(|r| order.iter().map(|&j| r[j]).collect())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, build, snapshot};
+    use crate::{MyAlloc, register};
+    use std::alloc::{GlobalAlloc, Layout};
+    use std::time::Instant;
+
+    #[test]
+    fn test_snapshot() {
+        let site = register(&[100]);
+        let l = Layout::from_size_align(1000, 8).unwrap();
+        let before = snapshot();
+        let p = unsafe { MyAlloc.alloc_site(l, (site, l.size()), false) };
+        let during = snapshot();
+        unsafe { MyAlloc.dealloc(p, l) };
+        let after = snapshot();
+        assert_eq!(before.live.get(site).copied().unwrap_or(0), 0);
+        assert_eq!(during.live[site], 1000);
+        assert_eq!(after.live[site], 0);
+        assert!(during.total_live >= 1000);
+    }
+
+    #[test]
+    fn test_build() {
+        // slots 1 and 3 resolve to the same callsite, slot 2 is filtered out
+        let keys = vec![
+            None,
+            Some("a\n".into()),
+            None,
+            Some("a\n".into()),
+            Some("b\n".into()),
+        ];
+        let snap = |live: Vec<usize>| Snapshot {
+            at: Instant::now(),
+            total_live: live.iter().sum(),
+            live,
+        };
+        let snaps = [
+            snap(vec![0, 10, 5, 1, 50]),
+            snap(vec![0, 30, 5, 2]),
+            snap(vec![0, 0, 0, 0, 0]),
+        ];
+        let t = build(&keys, &snaps, 0);
+        assert_eq!(t.sites, ["b", "a"]);
+        assert_eq!(t.live, [vec![50, 11], vec![0, 32], vec![0, 0]]);
+        assert_eq!(t.total_live, [66, 37, 0]);
+
+        let t = build(&keys, &snaps, 1);
+        assert_eq!(t.sites, ["b"]);
+        assert_eq!(t.live[1], [0]);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::{Entry, Logger, write_sinks};
+use log::{Level, Record};
+use std::cell::Cell;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::Duration;
+
+thread_local! {
+    /// set while the thread logs its own panic or crash, it may hold any lock of the logger
+    static G_CRASHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// how long a crashing thread waits for a lock before writing without it
+const PATIENCE: Duration = Duration::from_millis(100);
+
+/// lock `m`, giving up after a while when the calling thread is crashing since it may be the
+/// holder itself
+pub(crate) fn lock<T: ?Sized>(m: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    if !G_CRASHING.get() {
+        return Some(m.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+    let step = Duration::from_millis(1);
+    for _ in 0..PATIENCE.as_millis() {
+        if let Some(x) = try_lock(m) {
+            return Some(x);
+        }
+        std::thread::sleep(step);
+    }
+    None
+}
+
+/// lock `m` unless it's held, a poisoned lock is still usable
+pub(crate) fn try_lock<T: ?Sized>(m: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match m.try_lock() {
+        Ok(x) => Some(x),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// write `record` to every sink regardless of filters after whatever is queued, then flush
+/// them
+fn write_all(l: &Logger, record: &Record) {
+    G_CRASHING.set(true);
+    let state = l.state();
+    let line = state.line(record);
+    let _lk = l.queue.get().and_then(|q| {
+        let lk = q.crash_lock()?;
+        q.drain(&lk);
+        Some(lk)
+    });
+    let e = Entry {
+        state,
+        mask: u64::MAX,
+        line,
+    };
+    write_sinks(std::slice::from_ref(&e));
+    for p in &e.state.sinks {
+        p.flush();
+    }
+    G_CRASHING.set(false);
+}
+
+/// log panics with their thread and backtrace, the previous hook runs afterwards
+pub(crate) fn hook_panic(l: &'static Logger) {
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let bt = std::backtrace::Backtrace::force_capture();
+        let t = std::thread::current();
+        let msg = info.payload_as_str().unwrap_or("Box<dyn Any>");
+        let loc = info.location();
+        write_all(
+            l,
+            &Record::builder()
+                .args(format_args!(
+                    "thread '{}' panicked: {}\n{}",
+                    t.name().unwrap_or("<unnamed>"),
+                    msg,
+                    bt
+                ))
+                .level(Level::Error)
+                .target("panic")
+                .file(loc.map(|x| x.file()))
+                .line(loc.map(|x| x.line()))
+                .build(),
+        );
+        prev(info);
+    }));
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use signal::hook_signals;
+
+#[cfg(target_os = "linux")]
+mod signal {
+    use super::try_lock;
+    use crate::{Logger, SinkHandle};
+    use std::sync::OnceLock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// signals caught and the notice written for them, formatting isn't async signal safe so
+    /// the notice is plain text whatever the format
+    const SIGNALS: [(libc::c_int, &[u8]); 2] = [
+        (libc::SIGSEGV, b"caught SIGSEGV\n"),
+        (libc::SIGABRT, b"caught SIGABRT\n"),
+    ];
+
+    static G_TARGET: OnceLock<&'static Logger> = OnceLock::new();
+    /// handlers replaced, the signal is passed on to them
+    static G_OLD: OnceLock<Vec<(libc::c_int, libc::sigaction)>> = OnceLock::new();
+    static G_FIRED: AtomicBool = AtomicBool::new(false);
+
+    /// only the first call takes effect
+    pub(crate) fn hook_signals(l: &'static Logger) {
+        if G_TARGET.set(l).is_err() {
+            return;
+        }
+        let old = SIGNALS
+            .iter()
+            .map(|&(sig, _)| unsafe {
+                let mut act: libc::sigaction = std::mem::zeroed();
+                act.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+                // the stack may have overflowed, std sets up an alternate one for every thread
+                act.sa_flags = libc::SA_ONSTACK | libc::SA_SIGINFO;
+                libc::sigemptyset(&mut act.sa_mask);
+                let mut old: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(sig, &act, &mut old);
+                (sig, old)
+            })
+            .collect();
+        let _ = G_OLD.set(old);
+    }
+
+    /// write `s` to the descriptor of `p` unless it's busy or has none
+    fn write_fd(p: &SinkHandle, mut s: &[u8]) {
+        let Some(fd) = try_lock(&p.sink).and_then(|x| x.fd()) else {
+            return;
+        };
+        while !s.is_empty() {
+            let n = unsafe { libc::write(fd, s.as_ptr().cast(), s.len()) };
+            if n <= 0 {
+                return;
+            }
+            s = &s[n as usize..];
+        }
+    }
+
+    /// the interrupted thread may hold any lock, even the allocator's, so locks are only tried
+    /// and nothing is allocated or freed, queued records are written as formatted when logged
+    /// followed by the notice, a sink busy at the time or without a descriptor gets neither
+    extern "C" fn on_signal(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+        if !G_FIRED.swap(true, Ordering::SeqCst)
+            && let Some(l) = G_TARGET.get()
+        {
+            if let Some(q) = l.queue.get() {
+                q.peek(|e| {
+                    for (i, p) in e.state.sinks.iter().enumerate() {
+                        if e.mask & (1 << i) != 0 {
+                            write_fd(p, e.line.as_bytes());
+                        }
+                    }
+                });
+            }
+            if let Some(&(_, notice)) = SIGNALS.iter().find(|x| x.0 == sig)
+                && let Ok(state) = l.state.try_read()
+            {
+                for p in &state.sinks {
+                    write_fd(p, notice);
+                }
+            }
+        }
+        let Some((_, old)) = G_OLD.get().and_then(|v| v.iter().find(|x| x.0 == sig)) else {
+            return;
+        };
+        unsafe { libc::sigaction(sig, old, std::ptr::null_mut()) };
+        match old.sa_sigaction {
+            // the signal is blocked until we return, it takes its course then
+            libc::SIG_DFL | libc::SIG_IGN => unsafe {
+                libc::raise(sig);
+            },
+            // e.g. std's stack overflow detection, which needs the fault address
+            f if old.sa_flags & libc::SA_SIGINFO != 0 => unsafe {
+                let f: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                    std::mem::transmute(f);
+                f(sig, info, ctx);
+            },
+            f => unsafe {
+                let f: extern "C" fn(libc::c_int) = std::mem::transmute(f);
+                f(sig);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Logger, Memory};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_lock() {
+        let m = Mutex::new(1);
+        let g = m.lock().unwrap();
+        super::G_CRASHING.set(true);
+        assert!(super::lock(&m).is_none());
+        drop(g);
+        assert_eq!(*super::lock(&m).unwrap(), 1);
+        super::G_CRASHING.set(false);
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let m = Memory::new("panic");
+        let h = m.handle();
+        let l: &'static Logger = Box::leak(Box::new(Logger::builder().sink(m).build()));
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(|_| {}));
+        l.panic_hook();
+        let r = std::thread::Builder::new()
+            .name("doomed".into())
+            .spawn(|| panic!("boom {}", 42))
+            .unwrap()
+            .join();
+        std::panic::set_hook(prev);
+        assert!(r.is_err());
+        // panics of other tests may be logged meanwhile
+        let v = h.lines();
+        let line = v.iter().find(|x| x.contains("'doomed'")).unwrap();
+        assert!(line.contains(" [ERROR] src/crash.rs:"), "{}", line);
+        assert!(
+            line.ends_with(" thread 'doomed' panicked: boom 42"),
+            "{}",
+            line
+        );
+        // the backtrace
+        assert!(v.len() > 1);
+    }
+
+    /// run the calling test again in a child process which sets up a logger writing to a file,
+    /// installs the crash handler and calls `crash`, the file is returned once the child died
+    #[cfg(target_os = "linux")]
+    fn crash_child(test: &str, crash: impl FnOnce(&'static Logger)) -> (String, String) {
+        const ENV: &str = "LOGGER_CRASH_CHILD";
+        if let Ok(path) = std::env::var(ENV) {
+            let l: &'static Logger = Box::leak(Box::new(
+                Logger::builder()
+                    .async_mode(1024, crate::Overflow::Block)
+                    .build(),
+            ));
+            l.add_file(&path, true).unwrap();
+            l.add_sink(Memory::new("no fd"));
+            l.crash_handler();
+            crash(l);
+            unreachable!();
+        }
+        let path = std::env::temp_dir().join(format!("logger_{}_{}.log", test, std::process::id()));
+        let out = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", &format!("crash::test::{}", test), "--nocapture"])
+            .env(ENV, &path)
+            .output()
+            .unwrap();
+        assert!(!out.status.success());
+        let s = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        (s, String::from_utf8_lossy(&out.stderr).into_owned())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn log_line(l: &Logger, s: &str) {
+        log::Log::log(
+            l,
+            &log::Record::builder()
+                .args(format_args!("{}", s))
+                .level(log::Level::Info)
+                .build(),
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_crash_handler() {
+        let (s, _) = crash_child("test_crash_handler", |l| {
+            log_line(l, "written");
+            log::Log::flush(l);
+            log_line(l, "queued");
+            std::process::abort();
+        });
+        let v: Vec<_> = s.lines().collect();
+        assert_eq!(v.len(), 3, "{}", s);
+        assert!(v[0].ends_with(" written"));
+        assert!(v[1].ends_with(" queued"));
+        assert_eq!(v[2], "caught SIGABRT");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_stack_overflow() {
+        fn recurse(n: u64) -> u64 {
+            let x = std::hint::black_box([n; 64]);
+            if x[0] == u64::MAX {
+                return 0;
+            }
+            recurse(x[0] + 1) + x[1]
+        }
+        let (s, err) = crash_child("test_stack_overflow", |l| {
+            log_line(l, "queued");
+            recurse(0);
+        });
+        // std's handler still tells an overflow from other faults
+        assert!(err.contains("has overflowed its stack"), "{}", err);
+        let v: Vec<_> = s.lines().collect();
+        assert!(v[0].ends_with(" queued"), "{}", s);
+        assert_eq!(v[1], "caught SIGSEGV");
+    }
+}
@@ -0,0 +1,114 @@
+use log::{Level, LevelFilter};
+use std::str::FromStr;
+
+/// minimum level of records by target, the level of the longest matching module prefix wins,
+/// the default applies to targets matching none
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(LevelFilter::Trace)
+    }
+}
+
+impl Filter {
+    pub fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+
+    /// `prefix` matches a target equal to it or one of its submodules, e.g. `mace` matches
+    /// `mace::map` but not `macro`
+    pub fn module(mut self, prefix: &str, level: LevelFilter) -> Self {
+        self.modules.retain(|(x, _)| x != prefix);
+        self.modules.push((prefix.to_string(), level));
+        // longest first so the first match is the most specific
+        self.modules.sort_by_key(|x| std::cmp::Reverse(x.0.len()));
+        self
+    }
+
+    /// comma separated directives like `RUST_LOG`, a bare level sets the default and
+    /// `prefix=level` the level of a module, e.g. `warn,mace=debug,mace::map=trace`, the
+    /// default is error without a bare level so `mace=debug` doesn't let others through
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut r = Self::new(LevelFilter::Error);
+        for d in spec.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let level = |x: &str| {
+                LevelFilter::from_str(x.trim()).map_err(|_| format!("invalid level {x} in {d}"))
+            };
+            r = match d.split_once('=') {
+                Some((m, l)) => r.module(m.trim(), level(l)?),
+                None => match level(d) {
+                    Ok(l) => Self { default: l, ..r },
+                    // a bare module enables everything of it
+                    Err(_) => r.module(d, LevelFilter::Trace),
+                },
+            };
+        }
+        Ok(r)
+    }
+
+    /// the filter in env var `name`, `None` when it's not set
+    pub fn from_env(name: &str) -> Option<Result<Self, String>> {
+        std::env::var(name).ok().map(|x| Self::parse(&x))
+    }
+
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(m, _)| {
+                target
+                    .strip_prefix(m.as_str())
+                    .is_some_and(|x| x.is_empty() || x.starts_with("::"))
+            })
+            .map_or(self.default, |(_, l)| *l)
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.level(target)
+    }
+
+    /// the most verbose level any target gets
+    pub fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, l)| *l)
+            .fold(self.default, Ord::max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Filter;
+    use log::{Level, LevelFilter};
+
+    #[test]
+    fn test_filter() {
+        let f = Filter::parse("warn, mace=debug,mace::map=trace,kv").unwrap();
+        assert_eq!(f.level("other"), LevelFilter::Warn);
+        assert_eq!(f.level("mace"), LevelFilter::Debug);
+        assert_eq!(f.level("mace::index"), LevelFilter::Debug);
+        assert_eq!(f.level("mace::map::flush"), LevelFilter::Trace);
+        assert_eq!(f.level("macex"), LevelFilter::Warn);
+        assert_eq!(f.level("kv::x"), LevelFilter::Trace);
+        assert!(f.enabled(Level::Error, "other"));
+        assert!(!f.enabled(Level::Info, "other"));
+        assert_eq!(f.max(), LevelFilter::Trace);
+
+        let f = Filter::new(LevelFilter::Info).module("mace", LevelFilter::Off);
+        assert!(!f.enabled(Level::Error, "mace::map"));
+        assert_eq!(f.max(), LevelFilter::Info);
+        assert_eq!(Filter::parse("").unwrap(), Filter::new(LevelFilter::Error));
+        let f = Filter::parse("mace=debug").unwrap();
+        assert!(f.enabled(Level::Debug, "mace::map"));
+        assert!(!f.enabled(Level::Debug, "kv_bench"));
+        assert!(f.enabled(Level::Error, "kv_bench"));
+        assert!(Filter::parse("mace=loud").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use log::Record;
+use log::kv::{Error, Key, Value, VisitSource};
+use std::fmt::Write;
+
+/// turns a record into the bytes written to sinks
+pub trait Format: Send + Sync {
+    /// append the record logged by thread `tid` to `out`, including the trailing newline
+    fn format(&self, out: &mut String, tid: i32, record: &Record);
+}
+
+fn now() -> impl std::fmt::Display {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S.%6f")
+}
+
+/// `<time> <tid> [<level>] <file>:<line> <message>` followed by ` key=value` fields
+pub struct Text;
+
+impl Format for Text {
+    fn format(&self, out: &mut String, tid: i32, record: &Record) {
+        let _ = write!(
+            out,
+            "{} {} [{}] {}:{} {}",
+            now(),
+            tid,
+            record.level().as_str(),
+            record.file().unwrap_or("?"),
+            record.line().unwrap_or(0),
+            record.args()
+        );
+        let _ = record.key_values().visit(&mut TextFields(out));
+        out.push('\n');
+    }
+}
+
+struct TextFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for TextFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let _ = write!(self.0, " {}={}", key, value);
+        Ok(())
+    }
+}
+
+/// one json object per line with `time`, `tid`, `level`, `file`, `line`, `target` and `msg`,
+/// key-value fields go to `fields`
+pub struct Json;
+
+impl Format for Json {
+    fn format(&self, out: &mut String, tid: i32, record: &Record) {
+        let _ = write!(out, "{{\"time\":\"{}\",\"tid\":{},\"level\":", now(), tid);
+        escape(out, record.level().as_str());
+        out.push_str(",\"file\":");
+        escape(out, record.file().unwrap_or("?"));
+        let _ = write!(out, ",\"line\":{},\"target\":", record.line().unwrap_or(0));
+        escape(out, record.target());
+        out.push_str(",\"msg\":");
+        escape(out, &record.args().to_string());
+        if record.key_values().count() > 0 {
+            out.push_str(",\"fields\":{");
+            let _ = record.key_values().visit(&mut JsonFields {
+                out: &mut *out,
+                first: true,
+            });
+            out.push('}');
+        }
+        out.push_str("}\n");
+    }
+}
+
+struct JsonFields<'a> {
+    out: &'a mut String,
+    first: bool,
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        if !std::mem::take(&mut self.first) {
+            self.out.push(',');
+        }
+        escape(self.out, key.as_str());
+        self.out.push(':');
+        // numbers and booleans stay bare, anything else is a string
+        if let Some(x) = value.to_i64() {
+            let _ = write!(self.out, "{}", x);
+        } else if let Some(x) = value.to_u64() {
+            let _ = write!(self.out, "{}", x);
+        } else if let Some(x) = value.to_f64().filter(|x| x.is_finite()) {
+            let _ = write!(self.out, "{}", x);
+        } else if let Some(x) = value.to_bool() {
+            let _ = write!(self.out, "{}", x);
+        } else {
+            escape(self.out, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// `s` as a quoted json string
+fn escape(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Format, Json, Text};
+    use log::{Level, Record};
+
+    #[test]
+    fn test_format() {
+        let kv: &[(&str, &dyn log::kv::ToValue)] = &[("ops", &42), ("name", &"a\"b"), ("r", &0.5)];
+        let format = |f: &dyn Format| {
+            let mut s = String::new();
+            f.format(
+                &mut s,
+                3,
+                &Record::builder()
+                    .args(format_args!("hello\n{}", 1))
+                    .level(Level::Warn)
+                    .target("mace::map")
+                    .file(Some("src/map.rs"))
+                    .line(Some(7))
+                    .key_values(&kv)
+                    .build(),
+            );
+            s
+        };
+
+        let s = format(&Text);
+        assert!(
+            s.ends_with(" 3 [WARN] src/map.rs:7 hello\n1 ops=42 name=a\"b r=0.5\n"),
+            "{s}"
+        );
+
+        let s = format(&Json);
+        let (_, tail) = s.split_once("\",\"tid\"").unwrap();
+        assert_eq!(
+            tail,
+            ":3,\"level\":\"WARN\",\"file\":\"src/map.rs\",\"line\":7,\"target\":\"mace::map\",\
+             \"msg\":\"hello\\n1\",\"fields\":{\"ops\":42,\"name\":\"a\\\"b\",\"r\":0.5}}\n"
+        );
+        assert!(s.starts_with("{\"time\":\""));
+    }
+}
@@ -1,28 +1,34 @@
+mod crash;
+mod filter;
+mod format;
+mod queue;
+mod rotate;
+mod sink;
+
+pub use filter::Filter;
+pub use format::{Format, Json, Text};
+pub use queue::Overflow;
+pub use rotate::Rotation;
+#[cfg(unix)]
+pub use sink::Syslog;
+pub use sink::{Console, File, Memory, MemoryHandle, Sink};
+
 use log::{LevelFilter, Metadata, Record};
+use queue::Queue;
+use sink::G_CONSOLE;
 use std::cell::OnceCell;
-use std::io::Write;
-use std::ops::Deref;
 use std::path::Path;
-use std::ptr::addr_of_mut;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
-use std::sync::{Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
 
 thread_local! {
-    static G_TID: OnceCell<i32> = OnceCell::new();
+    static G_TID: OnceCell<i32> = const { OnceCell::new() };
 }
 #[cfg(not(target_os = "linux"))]
 static G_ID: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(1);
 
-static mut G_LOGGER: Logger = Logger {
-    mtx_shard: [const { Mutex::new(()) }; 8],
-    sink: Vec::new(),
-    abort_on_error: AtomicBool::new(false),
-};
-static G_INIIED: Mutex<bool> = Mutex::new(false);
-
-const G_CONSOLE: &'static str = "console";
-const G_FILE: &'static str = "file";
+static G_LOGGER: OnceLock<Logger> = OnceLock::new();
 
 #[cfg(target_os = "linux")]
 fn get_tid() -> i32 {
@@ -35,264 +41,751 @@ fn get_tid() -> i32 {
     G_TID.with(|x| *x.get_or_init(|| G_ID.fetch_add(1, Relaxed)))
 }
 
-/// a simple sync logger which impl log::Log
+/// a simple logger which impl log::Log, it's shared immutably, every reconfiguration publishes
+/// a new set of sinks and records already logged still go to the set they started with
 pub struct Logger {
-    mtx_shard: [Mutex<()>; 8],
-    sink: Vec<SinkHandle>,
+    state: Arc<RwLock<Arc<State>>>,
     abort_on_error: AtomicBool,
+    queue: OnceLock<Arc<Queue>>,
 }
 
-struct SinkHandle {
-    raw: *mut dyn Sink,
+/// a record carries one bit per sink it goes to
+const MAX_SINKS: usize = u64::BITS as usize;
+
+/// sinks and format at some point in time, never changed once published
+#[derive(Clone)]
+struct State {
+    sinks: Vec<SinkHandle>,
+    format: Arc<dyn Format>,
 }
 
-unsafe impl Send for SinkHandle {}
-unsafe impl Sync for SinkHandle {}
+impl State {
+    /// sinks accepting `m`
+    fn mask(&self, m: &Metadata) -> u64 {
+        let mut r = 0;
+        for (i, x) in self.sinks.iter().enumerate() {
+            if x.filter.enabled(m.level(), m.target()) {
+                r |= 1 << i;
+            }
+        }
+        r
+    }
 
-impl SinkHandle {
-    fn new<T>(x: T) -> Self
-    where
-        T: Sink + 'static,
-    {
-        let x = Box::new(x);
-        let raw = Box::into_raw(x);
-        Self { raw }
+    fn exist(&self, name: &str) -> bool {
+        self.sinks.iter().any(|x| *x.name == *name)
     }
 
-    fn as_mut(&self) -> &mut dyn Sink {
-        unsafe { &mut *self.raw }
+    /// add `sink` unless one of the same name exists or [`MAX_SINKS`] are there, why it's not
+    /// added is returned then
+    fn add(&mut self, sink: impl Sink + 'static) -> Result<(), String> {
+        if self.exist(sink.name()) {
+            return Err(format!("sink {} exists", sink.name()));
+        }
+        if self.sinks.len() == MAX_SINKS {
+            return Err(format!(
+                "sink {} refused, {} sinks at most",
+                sink.name(),
+                MAX_SINKS
+            ));
+        }
+        self.sinks.push(SinkHandle::new(sink));
+        Ok(())
     }
-}
 
-impl Deref for SinkHandle {
-    type Target = dyn Sink;
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.raw }
+    /// `record` as written to sinks
+    fn line(&self, record: &Record) -> String {
+        let mut line = String::new();
+        self.format.format(&mut line, get_tid(), record);
+        line
     }
-}
 
-impl Drop for SinkHandle {
-    fn drop(&mut self) {
-        unsafe {
-            let _ = Box::from_raw(self.raw);
+    /// write `record` to every sink regardless of filters
+    fn write_all(&self, record: &Record) {
+        let line = self.line(record);
+        for p in &self.sinks {
+            p.sink(&line);
         }
     }
+
+    fn max_level(&self) -> LevelFilter {
+        self.sinks
+            .iter()
+            .map(|x| x.filter.max())
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
 }
 
-trait Sink: Send + Sync {
-    fn sink(&mut self, str: &String);
+/// a sink shared by every state it's part of, the filter belongs to the state
+#[derive(Clone)]
+struct SinkHandle {
+    name: Arc<str>,
+    sink: Arc<Mutex<Box<dyn Sink>>>,
+    filter: Filter,
+}
 
-    fn flush(&mut self);
+impl SinkHandle {
+    fn new(x: impl Sink + 'static) -> Self {
+        Self {
+            name: x.name().into(),
+            sink: Arc::new(Mutex::new(Box::new(x))),
+            filter: Filter::default(),
+        }
+    }
+
+    /// a sink panicked while writing is still usable
+    fn lock(&self) -> Option<MutexGuard<'_, Box<dyn Sink>>> {
+        crash::lock(&self.sink)
+    }
+
+    fn sink(&self, s: &str) {
+        if let Some(mut x) = self.lock() {
+            x.sink(s);
+        }
+    }
 
-    fn name(&self) -> &'static str;
+    fn flush(&self) {
+        if let Some(mut x) = self.lock() {
+            x.flush();
+        }
+    }
+}
+
+/// a formatted record and the sinks of `state` it goes to, bit `i` stands for sink `i`
+pub(crate) struct Entry {
+    state: Arc<State>,
+    mask: u64,
+    line: String,
 }
 
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        return true;
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.state()
+            .sinks
+            .iter()
+            .any(|x| x.filter.enabled(metadata.level(), metadata.target()))
     }
 
     fn log(&self, record: &Record) {
-        let s = format!(
-            "{} {} [{}] {}:{} {}\n",
-            chrono::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            get_tid(),
-            record.level().as_str(),
-            record.file().unwrap(),
-            record.line().unwrap(),
-            record.args()
-        );
-        let _lk = self.lock();
-
-        for p in &self.sink {
-            p.as_mut().sink(&s);
+        let state = self.state();
+        let mask = state.mask(record.metadata());
+        if mask == 0 {
+            return;
+        }
+        let line = state.line(record);
+        let e = Entry { state, mask, line };
+        match self.queue.get() {
+            Some(q) => {
+                if let Err(e) = q.push(e) {
+                    let _lk = q.lock();
+                    write_sinks(&[e]);
+                }
+            }
+            None => write_sinks(&[e]),
         }
 
         if record.level() == log::LevelFilter::Error && self.should_abort() {
+            // queued records go first
+            log::Log::flush(self);
             let bt = std::backtrace::Backtrace::force_capture();
-            let buf = format!("{}", bt);
-            for p in &self.sink {
-                p.as_mut().sink(&buf);
+            let _lk = self.queue.get().map(|q| q.lock());
+            let state = self.state();
+            state.write_all(
+                &Record::builder()
+                    .args(format_args!("abort on error\n{}", bt))
+                    .level(log::Level::Error)
+                    .target(record.target())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            );
+            for p in &state.sinks {
+                p.flush();
             }
             std::process::abort();
         }
     }
 
     fn flush(&self) {
-        let _lk = self.lock();
-        for p in &self.sink {
-            p.as_mut().flush();
+        let _lk = self.queue.get().map(|q| {
+            let lk = q.lock();
+            q.drain(&lk);
+            lk
+        });
+        for p in &self.state().sinks {
+            p.flush();
         }
     }
 }
 
-struct Console {}
+/// each sink gets its records in one write, records of the same state are adjacent in
+/// practice, the caller must hold the queue lock in async mode
+fn write_sinks(records: &[Entry]) {
+    let mut buf = String::new();
+    for run in records.chunk_by(|x, y| Arc::ptr_eq(&x.state, &y.state)) {
+        for (i, p) in run[0].state.sinks.iter().enumerate() {
+            buf.clear();
+            for e in run {
+                if e.mask & (1 << i) != 0 {
+                    buf.push_str(&e.line);
+                }
+            }
+            if !buf.is_empty() {
+                p.sink(&buf);
+            }
+        }
+    }
+}
 
-impl Console {
-    fn new() -> Self {
-        Self {}
+#[cfg(target_os = "linux")]
+extern "C" fn shutdown_at_exit() {
+    if let Some(l) = G_LOGGER.get() {
+        l.shutdown();
     }
 }
 
-/// NOTE: file rolling is not support at present
-struct File {
-    w: std::fs::File,
+/// configures a [`Logger`] before anything is logged
+pub struct Builder {
+    state: State,
+    abort_on_error: bool,
+    async_mode: Option<(usize, Overflow)>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl File {
-    fn new(path: impl AsRef<Path>, trunc: bool) -> Result<Self, std::io::Error> {
-        let mut ops = std::fs::File::options();
-        ops.write(true).create(true);
-        if trunc {
-            ops.truncate(true);
-        } else {
-            ops.append(true);
+impl Builder {
+    /// no sink, records are formatted as [`Text`]
+    pub fn new() -> Self {
+        Self {
+            state: State {
+                sinks: Vec::new(),
+                format: Arc::new(Text),
+            },
+            abort_on_error: false,
+            async_mode: None,
         }
-        match ops.open(path) {
-            Err(e) => Err(e),
-            Ok(f) => Ok(Self { w: f }),
+    }
+
+    /// a sink named like one added before is ignored, so is any past the 64th
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        if let Err(e) = self.state.add(sink) {
+            eprintln!("{}", e);
         }
+        self
     }
-}
 
-impl Sink for Console {
-    fn sink(&mut self, str: &String) {
-        std::io::stdout().write(str.as_bytes()).unwrap();
+    /// see [`Logger::filter`]
+    pub fn filter(mut self, name: &str, f: Filter) -> Self {
+        for x in self.state.sinks.iter_mut().filter(|x| *x.name == *name) {
+            x.filter = f.clone();
+        }
+        self
     }
 
-    fn flush(&mut self) {
-        std::io::stdout().flush().unwrap();
+    pub fn format(mut self, f: impl Format + 'static) -> Self {
+        self.state.format = Arc::new(f);
+        self
     }
 
-    fn name(&self) -> &'static str {
-        G_CONSOLE
+    pub fn abort_on_error(mut self, flag: bool) -> Self {
+        self.abort_on_error = flag;
+        self
     }
-}
 
-impl Sink for File {
-    fn sink(&mut self, str: &String) {
-        self.w.write(str.as_bytes()).unwrap();
+    /// see [`Logger::async_mode`]
+    pub fn async_mode(mut self, capacity: usize, overflow: Overflow) -> Self {
+        self.async_mode = Some((capacity, overflow));
+        self
     }
 
-    fn flush(&mut self) {
-        self.w.flush().unwrap();
+    /// a logger that is not installed, records reach it through [`log::Log`] only
+    pub fn build(self) -> Logger {
+        let l = Logger {
+            state: Arc::new(RwLock::new(Arc::new(self.state))),
+            abort_on_error: AtomicBool::new(self.abort_on_error),
+            queue: OnceLock::new(),
+        };
+        if let Some((capacity, overflow)) = self.async_mode {
+            l.async_mode(capacity, overflow);
+        }
+        l
     }
 
-    fn name(&self) -> &'static str {
-        G_FILE
+    /// build the logger and make it the one of the `log` crate, `None` is returned if a
+    /// logger was installed
+    pub fn install(self) -> Option<&'static Logger> {
+        let mut fresh = false;
+        let l = G_LOGGER.get_or_init(|| {
+            fresh = true;
+            self.build()
+        });
+        if !fresh {
+            return None;
+        }
+        log::set_logger(l).unwrap();
+        log::set_max_level(l.state().max_level());
+        if l.queue.get().is_some() {
+            l.at_exit();
+        }
+        Some(l)
     }
 }
 
 impl Logger {
-    fn is_set() -> bool {
-        *G_INIIED.lock().unwrap()
+    pub fn builder() -> Builder {
+        Builder::new()
     }
-    pub fn init() -> &'static mut Self {
-        if !Self::is_set() {
-            *G_INIIED.lock().unwrap() = true;
-            log::set_logger(Self::get()).unwrap();
-            log::set_max_level(LevelFilter::Trace);
-        }
-        return Self::get();
+
+    /// the installed logger, a logger without sinks is installed if there's none
+    pub fn init() -> &'static Self {
+        Builder::new().install();
+        Self::get()
     }
 
-    pub fn get() -> &'static mut Self {
-        unsafe {
-            let a = addr_of_mut!(G_LOGGER);
-            return &mut *a;
+    /// the installed logger, panics if nothing was installed
+    pub fn get() -> &'static Self {
+        G_LOGGER.get().expect("no logger installed")
+    }
+
+    fn is_installed(&self) -> bool {
+        G_LOGGER.get().is_some_and(|x| std::ptr::eq(x, self))
+    }
+
+    fn state(&self) -> Arc<State> {
+        self.state.read().unwrap().clone()
+    }
+
+    /// publish a copy of the current state changed by `f`, updates never interleave
+    fn update<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        let mut lk = self.state.write().unwrap();
+        let mut s = State::clone(&lk);
+        let r = f(&mut s);
+        if self.is_installed() {
+            // the max level of the `log` crate follows the most verbose sink
+            log::set_max_level(s.max_level());
         }
+        *lk = Arc::new(s);
+        r
     }
 
-    fn exist(&self, sink: &'static str) -> Option<&mut Self> {
-        let _lk = self.mtx_shard[0].lock().unwrap();
-        for i in &self.sink {
-            if i.name() == sink {
-                return Some(Self::get());
+    fn exist(&self, name: &str) -> bool {
+        self.state().exist(name)
+    }
+
+    /// how records are turned into lines, [`Text`] by default
+    pub fn format(&self, f: impl Format + 'static) -> &Self {
+        let f: Arc<dyn Format> = Arc::new(f);
+        self.update(|s| s.format = f);
+        self
+    }
+
+    /// set the filter of the sink named `name`, every sink lets anything
+    /// through until its filter is set
+    pub fn filter(&self, name: &str, f: Filter) -> &Self {
+        self.update(|s| {
+            for x in s.sinks.iter_mut().filter(|x| *x.name == *name) {
+                x.filter = f.clone();
             }
-        }
-        return None;
+        });
+        self
     }
 
     fn should_abort(&self) -> bool {
         self.abort_on_error.load(Relaxed)
     }
 
-    fn lock(&'_ self) -> MutexGuard<'_, ()> {
-        self.mtx_shard[get_tid() as usize & (self.mtx_shard.len() - 1)]
-            .lock()
-            .unwrap()
+    pub fn abort_on_error(&self, flag: bool) -> &Self {
+        self.abort_on_error.store(flag, Relaxed);
+        self
     }
 
-    pub fn abort_on_error(&mut self, flag: bool) -> &mut Self {
-        self.abort_on_error.store(flag, Relaxed);
+    /// queue records on a bounded queue of `capacity` records per thread instead of writing
+    /// them, a background thread writes them in batches, `overflow` decides what a thread does
+    /// when its queue is full, only the first call takes effect
+    ///
+    /// queued records are written by [`log::Log::flush`], [`Logger::shutdown`], on drop and,
+    /// for the installed logger on linux, when the process exits through `exit`
+    pub fn async_mode(&self, capacity: usize, overflow: Overflow) -> &Self {
+        let mut fresh = false;
+        let q = self.queue.get_or_init(|| {
+            fresh = true;
+            let state = self.state.clone();
+            let write = move |batch: &[Entry], dropped: usize| {
+                write_sinks(batch);
+                if dropped > 0 {
+                    let state = state.read().unwrap().clone();
+                    state.write_all(
+                        &Record::builder()
+                            .args(format_args!("logger dropped {} records", dropped))
+                            .level(log::Level::Warn)
+                            .target("logger")
+                            .build(),
+                    );
+                }
+            };
+            Arc::new(Queue::new(capacity, overflow, Box::new(write)))
+        });
+        if fresh {
+            q.start();
+            if self.is_installed() {
+                self.at_exit();
+            }
+        }
         self
     }
 
-    pub fn add_console(&mut self) -> &mut Self {
-        if self.exist(G_CONSOLE).is_none() {
-            self.sink.push(SinkHandle::new(Console::new()));
+    fn at_exit(&self) {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::atexit(shutdown_at_exit);
+        }
+    }
+
+    /// write every queued record, stop the background writer and flush the sinks, records are
+    /// written synchronously afterwards
+    pub fn shutdown(&self) {
+        if let Some(q) = self.queue.get() {
+            q.shutdown();
         }
+        log::Log::flush(self);
+    }
+
+    /// log panics of every thread with the thread's name, id and a backtrace to all sinks
+    /// regardless of filters, and flush them, the hook set before runs afterwards
+    pub fn panic_hook(&'static self) -> &'static Self {
+        crash::hook_panic(self);
         self
     }
 
-    pub fn add_file(&mut self, path: impl AsRef<Path>, trunc: bool) -> Option<&mut Self> {
-        if self.exist(G_FILE).is_none() {
-            match File::new(&path, trunc) {
-                Err(e) => {
-                    eprintln!(
-                        "can't open {}, error {}",
-                        path.as_ref().to_str().unwrap(),
-                        e.to_string()
-                    );
-                    return None;
-                }
-                Ok(f) => {
-                    self.sink.push(SinkHandle::new(f));
-                    return Some(self);
-                }
-            }
+    /// on SIGSEGV or SIGABRT write what's queued and a plain notice to every sink backed by a
+    /// descriptor before the signal takes its course, only the first call takes effect
+    #[cfg(target_os = "linux")]
+    pub fn crash_handler(&'static self) -> &'static Self {
+        crash::hook_signals(self);
+        self
+    }
+
+    /// records discarded because a queue was full
+    pub fn dropped(&self) -> usize {
+        self.queue.get().map_or(0, |q| q.dropped())
+    }
+
+    /// add a sink unless one of the same name exists or there are 64 already, `None` is
+    /// returned then
+    pub fn add_sink(&self, sink: impl Sink + 'static) -> Option<&Self> {
+        let mut sink = Some(sink);
+        if let Err(e) = self.update(|s| s.add(sink.take().unwrap())) {
+            eprintln!("{}", e);
+            return None;
         }
         Some(self)
     }
 
-    fn remove_impl(&mut self, name: &'static str) {
-        let _lk = self.mtx_shard[0].lock().unwrap();
-        for (idx, s) in self.sink.iter().enumerate() {
-            if s.name() == name {
-                self.sink.remove(idx);
-                break;
+    pub fn add_console(&self) -> &Self {
+        if !self.exist(G_CONSOLE) {
+            self.add_sink(Console::new());
+        }
+        self
+    }
+
+    /// add a [`File`] sink named after `path`, nothing is done if it exists
+    pub fn add_file(&self, path: impl AsRef<Path>, trunc: bool) -> Option<&Self> {
+        self.add_file_impl(path.as_ref(), |p| File::new(p, trunc))
+    }
+
+    /// like [`Logger::add_file`] but the file rolls over as `rotation` says, the file is
+    /// appended to and its current size counts towards the limit
+    pub fn add_rolling_file(&self, path: impl AsRef<Path>, rotation: Rotation) -> Option<&Self> {
+        self.add_file_impl(path.as_ref(), |p| File::rolling(p, rotation))
+    }
+
+    fn add_file_impl(
+        &self,
+        path: &Path,
+        open: impl FnOnce(&Path) -> std::io::Result<File>,
+    ) -> Option<&Self> {
+        if self.exist(&path.display().to_string()) {
+            return Some(self);
+        }
+        match open(path) {
+            Err(e) => {
+                eprintln!("can't open {}, error {}", path.display(), e);
+                None
             }
+            Ok(f) => self.add_sink(f),
         }
     }
 
-    pub fn remove_file(&mut self) {
-        self.remove_impl(G_FILE);
+    /// remove the sink named `name` if any, records queued for it are written and it's
+    /// flushed
+    pub fn remove(&self, name: &str) -> &Self {
+        let old = self.update(|s| {
+            let idx = s.sinks.iter().position(|x| *x.name == *name)?;
+            Some(s.sinks.remove(idx))
+        });
+        if let Some(p) = old {
+            if let Some(q) = self.queue.get() {
+                q.drain(&q.lock());
+            }
+            p.flush();
+        }
+        self
+    }
+
+    pub fn remove_file(&self, path: impl AsRef<Path>) {
+        self.remove(&path.as_ref().display().to_string());
     }
 
-    pub fn remove_console(&mut self) {
-        self.remove_impl(G_CONSOLE);
+    pub fn remove_console(&self) {
+        self.remove(G_CONSOLE);
     }
 }
 
 impl Drop for Logger {
     fn drop(&mut self) {
-        let _lk = self.lock();
-        for p in &self.sink {
-            p.as_mut().flush();
-        }
+        self.shutdown();
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::Logger;
+    use crate::{Builder, Filter, Logger, Memory, Overflow, Text};
+    use log::LevelFilter;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
 
     #[test]
     fn test_console() {
         let l = Logger::init();
 
         let p = log::logger() as *const dyn log::Log;
-        let q = &*l as *const dyn log::Log;
+        let q = l as *const dyn log::Log;
         assert!(std::ptr::addr_eq(p, q));
     }
+
+    #[test]
+    fn test_capture() {
+        let m = Memory::new("capture");
+        let h = m.handle();
+        let l = Logger::init();
+        l.add_sink(m).unwrap();
+        // other tests log concurrently, only this target is captured
+        l.filter(
+            "capture",
+            Filter::new(LevelFilter::Off).module("capture_test", LevelFilter::Info),
+        );
+        assert!(l.add_sink(Memory::new("capture")).is_none());
+
+        log::info!(target: "capture_test", "hello {}", 1);
+        log::debug!(target: "capture_test", "hidden");
+        log::info!("elsewhere");
+        log::logger().flush();
+        let v = h.lines();
+        assert_eq!(v.len(), 1);
+        assert!(v[0].ends_with(" hello 1"), "{}", v[0]);
+
+        l.remove("capture");
+        log::info!(target: "capture_test", "gone");
+        log::logger().flush();
+        assert_eq!(h.lines().len(), 1);
+    }
+
+    fn log_to(l: &Logger, t: usize, i: usize) {
+        log::Log::log(
+            l,
+            &log::Record::builder()
+                .args(format_args!("r {} {}", t, i))
+                .level(log::Level::Info)
+                .target("reconf")
+                .build(),
+        );
+    }
+
+    /// each thread's records in `v` are in order, the number of records per thread is returned
+    fn check_order(v: &[String], nr_thread: usize) -> Vec<usize> {
+        (0..nr_thread)
+            .map(|t| {
+                let x: Vec<usize> = v
+                    .iter()
+                    .filter_map(|l| l.split_once(&format!(" r {} ", t)))
+                    .map(|(_, i)| i.parse().unwrap())
+                    .collect();
+                assert!(
+                    x.windows(2).all(|w| w[0] < w[1]),
+                    "thread {} out of order",
+                    t
+                );
+                x.len()
+            })
+            .collect()
+    }
+
+    fn reconfigure(b: Builder) {
+        const NR_THREAD: usize = 4;
+        const NR_RECORD: usize = 2000;
+        let m = Memory::new("keep");
+        let h = m.handle();
+        let l = b.sink(m).build();
+        let flaky = std::sync::Mutex::new(Vec::new());
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            for t in 0..NR_THREAD {
+                let l = &l;
+                s.spawn(move || {
+                    for i in 0..NR_RECORD {
+                        log_to(l, t, i);
+                    }
+                });
+            }
+            s.spawn(|| {
+                let mut n = 0;
+                while !done.load(Relaxed) {
+                    let m = Memory::new("flaky");
+                    flaky.lock().unwrap().push(m.handle());
+                    l.add_sink(m).unwrap();
+                    let level = [LevelFilter::Info, LevelFilter::Trace][n % 2];
+                    l.filter("keep", Filter::new(level)).format(Text);
+                    l.remove("flaky");
+                    n += 1;
+                }
+            });
+            // the reconfiguring thread stops once every writer is done
+            while h.lines().len() < NR_THREAD * NR_RECORD {
+                log::Log::flush(&l);
+                std::thread::yield_now();
+            }
+            done.store(true, Relaxed);
+        });
+
+        l.shutdown();
+        let v = h.lines();
+        assert_eq!(check_order(&v, NR_THREAD), [NR_RECORD; NR_THREAD]);
+        for f in flaky.into_inner().unwrap() {
+            let v = f.lines();
+            check_order(&v, NR_THREAD);
+            assert!(v.iter().all(|x| x.contains(" r ")));
+        }
+        assert_eq!(l.dropped(), 0);
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        reconfigure(Logger::builder());
+        reconfigure(Logger::builder().async_mode(64, Overflow::Block));
+    }
+
+    struct Boom;
+
+    impl crate::Sink for Boom {
+        fn sink(&mut self, _: &str) {
+            panic!("boom");
+        }
+
+        fn flush(&mut self) {}
+
+        fn name(&self) -> &str {
+            "boom"
+        }
+    }
+
+    #[test]
+    fn test_panicking_sink() {
+        let m = Memory::new("ok");
+        let h = m.handle();
+        let l = Logger::builder()
+            .sink(Boom)
+            .sink(m)
+            .async_mode(4, Overflow::Block)
+            .build();
+        // the writer survives, blocked producers go on and remove drains without panicking
+        for i in 0..100 {
+            log_to(&l, 0, i);
+        }
+        l.remove("boom");
+        log_to(&l, 0, 100);
+        l.shutdown();
+        assert!(h.contains(" r 0 100"));
+    }
+
+    #[test]
+    fn test_async() {
+        let path = std::env::temp_dir().join(format!("logger_async_{}.log", std::process::id()));
+        Logger::init()
+            .async_mode(16, Overflow::Block)
+            .add_file(&path, true)
+            .unwrap();
+
+        let h: Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        log::info!("async {} {}", t, i);
+                    }
+                })
+            })
+            .collect();
+        for x in h {
+            x.join().unwrap();
+        }
+        log::logger().flush();
+
+        let s = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        // a full queue blocks, so nothing is lost and each thread's order is kept
+        for t in 0..4 {
+            let v: Vec<usize> = s
+                .lines()
+                .filter_map(|l| l.split_once(&format!(" async {} ", t)))
+                .map(|(_, i)| i.parse().unwrap())
+                .collect();
+            assert_eq!(v, (0..1000).collect::<Vec<_>>());
+        }
+        assert_eq!(Logger::get().dropped(), 0);
+    }
+
+    #[test]
+    fn test_max_sinks() {
+        let mut b = Logger::builder();
+        for i in 0..=crate::MAX_SINKS {
+            b = b.sink(Memory::new(&format!("m{}", i)));
+        }
+        let l = b.build();
+        assert!(l.exist("m63"));
+        assert!(!l.exist("m64"));
+        assert!(l.add_sink(Memory::new("more")).is_none());
+        // a free slot is taken again, the last sink gets records like the others
+        let m = Memory::new("last");
+        let h = m.handle();
+        l.remove("m0");
+        assert!(l.add_sink(m).is_some());
+        log_to(&l, 0, 0);
+        assert!(h.contains(" r 0 0"));
+    }
+
+    #[test]
+    fn test_dropped_notice() {
+        let m = Memory::new("json");
+        let h = m.handle();
+        let l = Logger::builder()
+            .sink(m)
+            .format(crate::Json)
+            .async_mode(1, Overflow::Count)
+            .build();
+        for i in 0..100 {
+            log_to(&l, 0, i);
+        }
+        l.shutdown();
+        assert!(l.dropped() > 0);
+        // formatted like any other record
+        let v = h.lines();
+        let notice = v.iter().find(|x| x.contains("logger dropped")).unwrap();
+        assert!(notice.starts_with("{\"time\":"), "{}", notice);
+        assert!(notice.contains("\"level\":\"WARN\""), "{}", notice);
+        assert!(v.iter().all(|x| x.starts_with('{')));
+    }
 }
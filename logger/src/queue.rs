@@ -0,0 +1,335 @@
+use crate::Entry;
+use std::cell::{RefCell, UnsafeCell};
+use std::mem::MaybeUninit;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, fence};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{JoinHandle, Thread};
+use std::time::Duration;
+
+/// what a thread does when its queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// wait for the writer to make room
+    Block,
+    /// discard the record silently
+    Drop,
+    /// discard the record, the writer logs how many were discarded
+    Count,
+}
+
+/// bounded single producer single consumer queue, the producer is the owning thread and the
+/// consumer whoever holds the writer lock
+pub(crate) struct Ring<T> {
+    buf: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// next slot to pop, only moved by the consumer
+    head: AtomicUsize,
+    /// next slot to push, only moved by the producer
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    pub(crate) fn new(cap: usize) -> Self {
+        Self {
+            buf: (0..cap.max(1))
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire) - self.head.load(Ordering::Acquire)
+    }
+
+    /// must only be called by the producer, the record is handed back when full
+    pub(crate) fn push(&self, s: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail - self.head.load(Ordering::Acquire) == self.buf.len() {
+            return Err(s);
+        }
+        unsafe { (*self.buf[tail % self.buf.len()].get()).write(s) };
+        self.tail.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// hand every record to `f` leaving them queued, must only be called by a consumer
+    pub(crate) fn peek_all(&self, mut f: impl FnMut(&T)) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        for i in head..tail {
+            f(unsafe { (*self.buf[i % self.buf.len()].get()).assume_init_ref() });
+        }
+    }
+
+    /// must only be called by one consumer at a time
+    pub(crate) fn pop_all(&self, mut f: impl FnMut(T)) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        for i in head..tail {
+            let s = unsafe { (*self.buf[i % self.buf.len()].get()).assume_init_read() };
+            // released one by one so a blocked producer can go on early
+            self.head.store(i + 1, Ordering::Release);
+            f(s);
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        self.pop_all(drop);
+    }
+}
+
+thread_local! {
+    /// the calling thread's ring of every queue it logged to, keyed by queue id
+    static G_RING: RefCell<Vec<(usize, Arc<Ring<Entry>>)>> = const { RefCell::new(Vec::new()) };
+}
+
+static G_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// called with a batch of records and the number of records discarded since the last call
+pub(crate) type Write = Box<dyn Fn(&[Entry], usize) + Send + Sync>;
+
+/// state of the async mode
+pub(crate) struct Queue {
+    id: usize,
+    cap: usize,
+    overflow: Overflow,
+    rings: Mutex<Vec<Arc<Ring<Entry>>>>,
+    /// serializes consumers and every write to the sinks
+    writer: Mutex<()>,
+    stop: AtomicBool,
+    dropped: AtomicUsize,
+    /// dropped records already logged by [`Overflow::Count`]
+    reported: AtomicUsize,
+    write: Write,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    handle: Mutex<Option<Thread>>,
+}
+
+/// how long the writer sleeps when nobody wakes it
+const INTERVAL: Duration = Duration::from_millis(10);
+
+impl Queue {
+    /// `write` is called with batches of records while holding the writer lock
+    pub(crate) fn new(cap: usize, overflow: Overflow, write: Write) -> Self {
+        Self {
+            id: G_QUEUE_ID.fetch_add(1, Ordering::Relaxed),
+            cap,
+            overflow,
+            rings: Mutex::new(Vec::new()),
+            writer: Mutex::new(()),
+            stop: AtomicBool::new(false),
+            dropped: AtomicUsize::new(0),
+            reported: AtomicUsize::new(0),
+            write,
+            thread: Mutex::new(None),
+            handle: Mutex::new(None),
+        }
+    }
+
+    /// the writer keeps the queue alive until [`Queue::shutdown`]
+    pub(crate) fn start(self: &Arc<Self>) {
+        let this = self.clone();
+        let h = std::thread::Builder::new()
+            .name("logger".into())
+            .spawn(move || {
+                loop {
+                    let stop = this.stopped();
+                    this.drain(&this.lock());
+                    if stop {
+                        break;
+                    }
+                    std::thread::park_timeout(INTERVAL);
+                }
+            })
+            .unwrap();
+        *self.handle.lock().unwrap_or_else(PoisonError::into_inner) = Some(h.thread().clone());
+        *self.thread.lock().unwrap_or_else(PoisonError::into_inner) = Some(h);
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// see [`crate::crash::lock`]
+    pub(crate) fn crash_lock(&self) -> Option<MutexGuard<'_, ()>> {
+        crate::crash::lock(&self.writer)
+    }
+
+    pub(crate) fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn wake(&self) {
+        if let Some(h) = self
+            .handle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+        {
+            h.unpark();
+        }
+    }
+
+    /// queue `s` on the calling thread's ring, it's handed back when it has to be written
+    /// synchronously, i.e. after stop or while the thread is being torn down
+    pub(crate) fn push(&self, mut s: Entry) -> Result<(), Entry> {
+        if self.stopped() {
+            return Err(s);
+        }
+        let ring = G_RING.try_with(|x| {
+            let mut v = x.borrow_mut();
+            if let Some((_, r)) = v.iter().find(|(id, _)| *id == self.id) {
+                return r.clone();
+            }
+            // rings of dropped queues
+            v.retain(|(_, r)| Arc::strong_count(r) > 1);
+            let r = Arc::new(Ring::new(self.cap));
+            self.rings
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(r.clone());
+            v.push((self.id, r.clone()));
+            r
+        });
+        let Ok(ring) = ring else {
+            return Err(s);
+        };
+        loop {
+            match ring.push(s) {
+                Ok(()) => {
+                    // pairs with the fence in shutdown, either the final drain sees the record
+                    // or the record sees the stop and drains itself
+                    fence(Ordering::SeqCst);
+                    if self.stopped() {
+                        self.drain(&self.lock());
+                    } else if ring.len() * 2 >= self.cap {
+                        self.wake();
+                    }
+                    return Ok(());
+                }
+                Err(x) => match self.overflow {
+                    Overflow::Block => {
+                        if self.stopped() {
+                            return Err(x);
+                        }
+                        self.wake();
+                        std::thread::yield_now();
+                        s = x;
+                    }
+                    Overflow::Drop | Overflow::Count => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                },
+            }
+        }
+    }
+
+    /// write everything queued so far as one batch, rings of exited threads are released once
+    /// empty, a panicking sink loses the batch but must neither stop the writer, blocked
+    /// producers would wait forever, nor unwind into whoever drains on its behalf
+    pub(crate) fn drain(&self, _lk: &MutexGuard<'_, ()>) {
+        let rings: Vec<_> = {
+            let mut lk = self.rings.lock().unwrap_or_else(PoisonError::into_inner);
+            lk.retain(|x| Arc::strong_count(x) > 1 || x.len() > 0);
+            lk.clone()
+        };
+        let mut batch = Vec::new();
+        for r in &rings {
+            r.pop_all(|s| batch.push(s));
+        }
+        let mut dropped = 0;
+        if self.overflow == Overflow::Count {
+            let n = self.dropped();
+            dropped = n - self.reported.swap(n, Ordering::Relaxed);
+        }
+        if !batch.is_empty() || dropped > 0 {
+            let _ = std::panic::catch_unwind(AssertUnwindSafe(|| (self.write)(&batch, dropped)));
+        }
+    }
+
+    /// hand every queued record to `f` without taking it out, nothing is done if a lock is
+    /// held, for a signal handler which must neither block nor free memory
+    pub(crate) fn peek(&self, mut f: impl FnMut(&Entry)) {
+        let (Some(_lk), Some(rings)) = (
+            crate::crash::try_lock(&self.writer),
+            crate::crash::try_lock(&self.rings),
+        ) else {
+            return;
+        };
+        for r in rings.iter() {
+            r.peek_all(&mut f);
+        }
+    }
+
+    /// stop queueing, wait for the writer to write everything queued and exit
+    pub(crate) fn shutdown(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        self.wake();
+        let h = self
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(h) = h
+            && h.thread().id() != std::thread::current().id()
+        {
+            let _ = h.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ring;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ring() {
+        let r = Ring::new(4);
+        for i in 0..4 {
+            assert!(r.push(i.to_string()).is_ok());
+        }
+        assert_eq!(r.push("x".into()), Err("x".into()));
+        let mut v = Vec::new();
+        r.pop_all(|s| v.push(s));
+        assert_eq!(v, ["0", "1", "2", "3"]);
+        assert_eq!(r.len(), 0);
+
+        // wraps around while a consumer keeps up
+        let r = Arc::new(Ring::new(8));
+        let p = {
+            let r = r.clone();
+            std::thread::spawn(move || {
+                for i in 0..10000 {
+                    let mut s = i.to_string();
+                    while let Err(x) = r.push(s) {
+                        s = x;
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+        let mut next = 0;
+        while next < 10000 {
+            r.pop_all(|s| {
+                assert_eq!(s, next.to_string());
+                next += 1;
+            });
+        }
+        p.join().unwrap();
+        r.push("left".into()).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use chrono::NaiveDate;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// when and how a file sink rolls over, the current file is renamed to `<path>.1`, older ones
+/// shift to `<path>.2` and so on, with `.gz` appended when compressed
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+    gzip: bool,
+}
+
+impl Rotation {
+    /// never rolls over until a trigger is set, no rotated file is kept
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// roll over before a write would take the file past `bytes`
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// roll over on the first write of a new local day
+    pub fn daily(mut self, flag: bool) -> Self {
+        self.daily = flag;
+        self
+    }
+
+    /// number of rotated files retained, older ones are removed
+    pub fn keep(mut self, n: usize) -> Self {
+        self.keep = n;
+        self
+    }
+
+    /// compress rotated files
+    pub fn gzip(mut self, flag: bool) -> Self {
+        self.gzip = flag;
+        self
+    }
+
+    /// whether a file of `size` bytes opened on `day` must roll over before writing `len`
+    /// bytes on `today`
+    pub(crate) fn due(&self, size: u64, len: usize, day: NaiveDate, today: NaiveDate) -> bool {
+        let full = self
+            .max_size
+            .is_some_and(|x| size > 0 && size + len as u64 > x);
+        full || (self.daily && today != day)
+    }
+
+    /// move `path` out of the way, the caller reopens it then calls [`Rotation::finish`], every
+    /// step is a rename so a reader never sees a partial file
+    pub(crate) fn rotate(&self, path: &Path) -> std::io::Result<()> {
+        let old = |i, gz| rotated(path, i, gz);
+        for gz in [false, true] {
+            remove(&old(self.keep.max(1), gz))?;
+            for i in (1..self.keep).rev() {
+                let from = old(i, gz);
+                if from.exists() {
+                    std::fs::rename(&from, old(i + 1, gz))?;
+                }
+            }
+        }
+        if self.keep == 0 {
+            return remove(path);
+        }
+        std::fs::rename(path, old(1, false))
+    }
+
+    /// compress the file moved by [`Rotation::rotate`] if asked to, `path` must be reopened by
+    /// then so nothing is written to the moved file meanwhile
+    pub(crate) fn finish(&self, path: &Path) -> std::io::Result<()> {
+        if self.keep == 0 || !self.gzip {
+            return Ok(());
+        }
+        compress(&rotated(path, 1, false), &rotated(path, 1, true))
+    }
+}
+
+/// the `i`th rotated file of `path`
+fn rotated(path: &Path, i: usize, gz: bool) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(format!(".{i}"));
+    if gz {
+        s.push(".gz");
+    }
+    PathBuf::from(s)
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// `from` is replaced by `to` only once it's completely written
+fn compress(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut w =
+        flate2::write::GzEncoder::new(std::fs::File::create(&tmp)?, flate2::Compression::default());
+    std::io::copy(&mut std::fs::File::open(from)?, &mut w)?;
+    w.finish()?.flush()?;
+    std::fs::rename(&tmp, to)?;
+    std::fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rotation;
+    use chrono::NaiveDate;
+    use std::io::Read;
+
+    #[test]
+    fn test_due() {
+        let d = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let next = d.succ_opt().unwrap();
+        let r = Rotation::new().max_size(100);
+        assert!(!r.due(0, 200, d, d));
+        assert!(!r.due(50, 50, d, next));
+        assert!(r.due(51, 50, d, d));
+        let r = r.daily(true);
+        assert!(r.due(0, 1, d, next));
+        assert!(!Rotation::new().due(1 << 40, 1, d, next));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("logger_rotate_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("x.log");
+
+        let r = Rotation::new().keep(2).gzip(true);
+        for i in 0..4 {
+            std::fs::write(&path, format!("gen {i}\n")).unwrap();
+            r.rotate(&path).unwrap();
+            r.finish(&path).unwrap();
+        }
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["x.log.1.gz", "x.log.2.gz"]);
+        let mut s = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(dir.join("x.log.2.gz")).unwrap())
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "gen 2\n");
+
+        // nothing kept
+        std::fs::write(&path, "x").unwrap();
+        Rotation::new().rotate(&path).unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use crate::Rotation;
+use chrono::NaiveDate;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// where formatted records go, sinks are told apart by name so a logger never holds two sinks
+/// of the same name
+pub trait Sink: Send + Sync {
+    /// `s` holds one or more whole lines
+    fn sink(&mut self, s: &str);
+
+    fn flush(&mut self);
+
+    fn name(&self) -> &str;
+
+    /// the descriptor written to if any, on a crash records and a notice go there directly, so
+    /// a sink with one must not buffer what it writes
+    #[cfg(target_os = "linux")]
+    fn fd(&self) -> Option<std::os::fd::RawFd> {
+        None
+    }
+}
+
+pub(crate) const G_CONSOLE: &str = "console";
+
+/// stdout, named `console`
+pub struct Console {}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink for Console {
+    fn sink(&mut self, s: &str) {
+        let _ = std::io::stdout().write_all(s.as_bytes());
+    }
+
+    fn flush(&mut self) {
+        let _ = std::io::stdout().flush();
+    }
+
+    fn name(&self) -> &str {
+        G_CONSOLE
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fd(&self) -> Option<std::os::fd::RawFd> {
+        Some(libc::STDOUT_FILENO)
+    }
+}
+
+/// a file, optionally rolling over, named after its path unless renamed
+pub struct File {
+    w: std::fs::File,
+    name: String,
+    path: PathBuf,
+    /// bytes in the current file
+    size: u64,
+    /// local day the current file was opened
+    day: NaiveDate,
+    rotation: Option<Rotation>,
+    /// the last write failed, errors are reported once until a write succeeds again
+    failed: bool,
+    /// the file was moved by a rotation but `path` couldn't be reopened, records are lost
+    /// rather than written to a rotated file until it is
+    stale: bool,
+}
+
+impl File {
+    pub fn new(path: impl AsRef<Path>, trunc: bool) -> Result<Self, std::io::Error> {
+        let path = path.as_ref();
+        let w = Self::open(path, trunc)?;
+        let m = w.metadata()?;
+        // an existing file belongs to the day it was last written
+        let day = match m.modified() {
+            Ok(x) if m.len() > 0 => chrono::DateTime::<chrono::Local>::from(x).date_naive(),
+            _ => chrono::Local::now().date_naive(),
+        };
+        Ok(Self {
+            size: m.len(),
+            w,
+            name: path.display().to_string(),
+            path: path.to_path_buf(),
+            day,
+            rotation: None,
+            failed: false,
+            stale: false,
+        })
+    }
+
+    /// the file is appended to and rolls over as `rotation` says, its current size counts
+    /// towards the limit
+    pub fn rolling(path: impl AsRef<Path>, rotation: Rotation) -> Result<Self, std::io::Error> {
+        let mut r = Self::new(path, false)?;
+        r.rotation = Some(rotation);
+        Ok(r)
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    fn open(path: &Path, trunc: bool) -> Result<std::fs::File, std::io::Error> {
+        let mut ops = std::fs::File::options();
+        ops.write(true).create(true);
+        if trunc {
+            ops.truncate(true);
+        } else {
+            ops.append(true);
+        }
+        ops.open(path)
+    }
+
+    /// a full disk or an io error must not take the process down, the records are lost
+    fn check(&mut self, r: std::io::Result<()>) {
+        match r {
+            Err(e) => self.fail("write", e),
+            Ok(()) => self.failed = false,
+        }
+    }
+
+    /// report `e` unless an error was reported since the last successful write
+    fn fail(&mut self, what: &str, e: std::io::Error) {
+        if !self.failed {
+            eprintln!("can't {} {:?}, error {}", what, self.path, e);
+            self.failed = true;
+        }
+    }
+
+    /// roll over if `len` more bytes are due to, a failure before the file is moved keeps
+    /// writing to it
+    fn roll(&mut self, len: usize) {
+        let Some(r) = self.rotation.clone() else {
+            return;
+        };
+        let today = chrono::Local::now().date_naive();
+        if self.stale {
+            self.reopen(today);
+            return;
+        }
+        if !r.due(self.size, len, self.day, today) {
+            return;
+        }
+        let _ = self.w.flush();
+        if let Err(e) = r.rotate(&self.path) {
+            self.fail("rotate", e);
+            // don't retry on every write
+            self.day = today;
+            self.size = 0;
+            return;
+        }
+        self.reopen(today);
+        if !self.stale
+            && let Err(e) = r.finish(&self.path)
+        {
+            self.fail("compress", e);
+        }
+    }
+
+    fn reopen(&mut self, today: NaiveDate) {
+        match Self::open(&self.path, true) {
+            Ok(w) => {
+                self.w = w;
+                self.size = 0;
+                self.day = today;
+                self.stale = false;
+            }
+            Err(e) => {
+                self.fail("reopen", e);
+                self.stale = true;
+            }
+        }
+    }
+}
+
+impl Sink for File {
+    fn sink(&mut self, s: &str) {
+        self.roll(s.len());
+        if self.stale {
+            return;
+        }
+        let r = self.w.write_all(s.as_bytes());
+        if r.is_ok() {
+            self.size += s.len() as u64;
+        }
+        self.check(r);
+    }
+
+    fn flush(&mut self) {
+        let r = self.w.flush();
+        self.check(r);
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fd(&self) -> Option<std::os::fd::RawFd> {
+        use std::os::fd::AsRawFd;
+        Some(self.w.as_raw_fd())
+    }
+}
+
+/// lines kept in memory, e.g. to assert on logs in tests or to dump the last lines on demand
+pub struct Memory {
+    name: String,
+    cap: Option<usize>,
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Memory {
+    /// keeps every line
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            cap: None,
+            lines: Arc::default(),
+        }
+    }
+
+    /// keeps the last `cap` lines
+    pub fn bounded(name: &str, cap: usize) -> Self {
+        Self {
+            cap: Some(cap),
+            ..Self::new(name)
+        }
+    }
+
+    /// reads the lines after the sink is handed to a logger
+    pub fn handle(&self) -> MemoryHandle {
+        MemoryHandle(self.lines.clone())
+    }
+}
+
+impl Sink for Memory {
+    fn sink(&mut self, s: &str) {
+        let mut lk = self.lines.lock().unwrap();
+        lk.extend(s.lines().map(str::to_string));
+        if let Some(cap) = self.cap {
+            let n = lk.len().saturating_sub(cap);
+            lk.drain(..n);
+        }
+    }
+
+    fn flush(&mut self) {}
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryHandle(Arc<Mutex<VecDeque<String>>>);
+
+impl MemoryHandle {
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn contains(&self, pat: &str) -> bool {
+        self.0.lock().unwrap().iter().any(|x| x.contains(pat))
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// a local syslog daemon listening on a unix datagram socket, every line is sent with the
+/// same severity, pair several sinks with filters to map levels
+#[cfg(unix)]
+pub struct Syslog {
+    name: String,
+    ident: String,
+    sock: std::os::unix::net::UnixDatagram,
+    severity: u8,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    /// `path` is usually `/dev/log`, lines are tagged `ident[pid]` with the user facility and
+    /// the info severity
+    pub fn connect(name: &str, path: impl AsRef<Path>, ident: &str) -> std::io::Result<Self> {
+        let sock = std::os::unix::net::UnixDatagram::unbound()?;
+        sock.connect(path)?;
+        Ok(Self {
+            name: name.to_string(),
+            ident: ident.to_string(),
+            sock,
+            severity: 6,
+        })
+    }
+
+    pub fn severity(mut self, level: log::Level) -> Self {
+        self.severity = match level {
+            log::Level::Error => 3,
+            log::Level::Warn => 4,
+            log::Level::Info => 6,
+            log::Level::Debug | log::Level::Trace => 7,
+        };
+        self
+    }
+}
+
+#[cfg(unix)]
+impl Sink for Syslog {
+    fn sink(&mut self, s: &str) {
+        // facility user is 1
+        let pri = 8 + self.severity;
+        let pid = std::process::id();
+        for l in s.lines() {
+            let msg = format!("<{}>{}[{}]: {}", pri, self.ident, pid, l);
+            // a missing daemon must not break logging
+            let _ = self.sock.send(msg.as_bytes());
+        }
+    }
+
+    fn flush(&mut self) {}
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{File, Memory, Sink};
+    use crate::Rotation;
+
+    #[test]
+    fn test_rolling_file() {
+        let dir = std::env::temp_dir().join(format!("logger_rolling_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("x.log");
+        let rotation = Rotation::new().max_size(64).daily(true).keep(1);
+        let mut f = File::rolling(&path, rotation).unwrap();
+        assert_eq!(f.name(), path.display().to_string());
+        let line = format!("{}\n", "x".repeat(39));
+        for _ in 0..3 {
+            f.sink(&line);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), line);
+        assert_eq!(std::fs::read_to_string(dir.join("x.log.1")).unwrap(), line);
+
+        // a new day rolls over regardless of size
+        f.day = f.day.pred_opt().unwrap();
+        f.sink("y\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "y\n");
+
+        // a file last written yesterday rolls over on the first write after a restart
+        drop(f);
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(86400);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(yesterday)
+            .unwrap();
+        let mut f = File::rolling(&path, Rotation::new().daily(true).keep(1)).unwrap();
+        f.sink("z\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "z\n");
+        assert_eq!(std::fs::read_to_string(dir.join("x.log.1")).unwrap(), "y\n");
+
+        // the file is reopened before compressing, a failing compression leaves the rotated
+        // file as is and later records go to the new one
+        drop(f);
+        let rotation = Rotation::new().max_size(2).keep(1).gzip(true);
+        let mut f = File::rolling(&path, rotation).unwrap();
+        std::fs::create_dir(dir.join("x.log.1.gz.tmp")).unwrap();
+        f.sink("a\n");
+        assert!(!f.stale);
+        assert!(!dir.join("x.log.1.gz").exists());
+        assert_eq!(std::fs::read_to_string(dir.join("x.log.1")).unwrap(), "z\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_full_disk() {
+        let mut f = File::new("/dev/full", false).unwrap();
+        f.sink("x\n");
+        assert!(f.failed);
+        f.flush();
+        assert_eq!(f.size, 0);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut m = Memory::bounded("ring", 3);
+        let h = m.handle();
+        m.sink("a\nb\n");
+        m.sink("c\nd\n");
+        assert_eq!(h.lines(), ["b", "c", "d"]);
+        assert!(h.contains("c") && !h.contains("a"));
+        h.clear();
+        assert!(h.lines().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_syslog() {
+        let path = std::env::temp_dir().join(format!("logger_syslog_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let mut s = super::Syslog::connect("syslog", &path, "kv")
+            .unwrap()
+            .severity(log::Level::Warn);
+        s.sink("one\ntwo\n");
+        let mut buf = [0u8; 256];
+        for x in ["one", "two"] {
+            let n = server.recv(&mut buf).unwrap();
+            let want = format!("<12>kv[{}]: {}", std::process::id(), x);
+            assert_eq!(std::str::from_utf8(&buf[..n]).unwrap(), want);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use coreid::{BindGuard, Placement, Topology};
+use std::sync::OnceLock;
+
+/// cpu of each thread id, unset means threads are pinned to `tid % cores_online`
+static G_CPUS: OnceLock<Vec<usize>> = OnceLock::new();
+
+/// resolve an `--affinity` policy against the machine topology for `threads` threads, cpus in
+/// `reserved` are left to background threads, without a policy workers take the remaining
+/// allowed cpus in order
+pub fn init(
+    spec: Option<&str>,
+    reserved: &[usize],
+    threads: usize,
+) -> Result<&'static [usize], String> {
+    if !coreid::supports_affinity() {
+        return Err("thread affinity is not supported on this platform".into());
+    }
+    let policy = match spec {
+        Some(spec) => Placement::parse(spec).ok_or_else(|| format!("invalid affinity {spec}"))?,
+        None => Placement::List(
+            coreid::allowed_cpus()
+                .map_err(|e| format!("can't get allowed cpus, {e}"))?
+                .into_iter()
+                .filter(|x| !reserved.contains(x))
+                .collect(),
+        ),
+    };
+    let topo = Topology::detect().map_err(|e| format!("can't detect cpu topology, {e}"))?;
+    if let Some(x) = reserved
+        .iter()
+        .find(|&&x| topo.cpus().iter().all(|c| c.id != x))
+    {
+        return Err(format!("reserved cpu {x} is not online"));
+    }
+    let topo = Topology::from_cpus(
+        topo.cpus()
+            .iter()
+            .filter(|c| !reserved.contains(&c.id))
+            .copied()
+            .collect(),
+    );
+    match &policy {
+        Placement::List(list) if list.is_empty() => {
+            return Err("no cpu left for workers".into());
+        }
+        Placement::List(list) => {
+            if let Some(x) = list.iter().find(|&&x| reserved.contains(&x)) {
+                return Err(format!("cpu {x} is reserved"));
+            }
+            if let Some(x) = list
+                .iter()
+                .find(|&&x| topo.cpus().iter().all(|c| c.id != x))
+            {
+                return Err(format!("cpu {x} is not online"));
+            }
+        }
+        _ if topo.cpus().is_empty() => return Err("no cpu left for workers".into()),
+        _ => {}
+    }
+    let cpus = topo.place(&policy, threads);
+    Ok(G_CPUS.get_or_init(|| cpus))
+}
+
+/// confine every thread of the process to `cpus`, the calling one included so that threads
+/// spawned afterwards, by the engine or the harness, inherit the confinement, workers escape it
+/// by pinning themselves with [`bind`], returns the confined threads
+pub fn confine(cpus: &[usize]) -> Result<Vec<(usize, String)>, String> {
+    let tids = coreid::threads().map_err(|e| format!("can't list threads, {e}"))?;
+    let mut r = Vec::new();
+    for tid in tids {
+        // the thread may have exited since the listing
+        let Some(name) = coreid::thread_name(tid) else {
+            continue;
+        };
+        match coreid::bind_thread(tid, cpus) {
+            Ok(()) => r.push((tid, name)),
+            Err(e) => log::warn!("can't confine thread {tid} {name}, {e}"),
+        }
+    }
+    Ok(r)
+}
+
+/// pin the current thread which is the `tid`-th of its group until the guard is dropped, a
+/// failure is logged and the thread keeps floating, it's a no-op where affinity is unsupported
+pub fn bind(tid: usize) -> Option<BindGuard> {
+    if !coreid::supports_affinity() {
+        return None;
+    }
+    let r = match G_CPUS.get() {
+        Some(cpus) => coreid::bind_cpu(cpus[tid % cpus.len()]),
+        None => coreid::bind_core(tid),
+    };
+    r.map_err(|e| log::warn!("can't pin thread {tid}, {e}"))
+        .ok()
+}
@@ -0,0 +1,110 @@
+//! the global allocator, custom_alloc traces on top of the system allocator, jemalloc, mimalloc
+//! and arena_alloc replace the system allocator, count_alloc counts whatever is chosen, the
+//! tracing one included
+
+#[cfg(any(
+    all(feature = "jemalloc", feature = "mimalloc"),
+    all(feature = "jemalloc", feature = "arena_alloc"),
+    all(feature = "mimalloc", feature = "arena_alloc"),
+))]
+compile_error!("jemalloc, mimalloc and arena_alloc are exclusive");
+
+#[cfg(all(
+    feature = "custom_alloc",
+    any(
+        feature = "jemalloc",
+        feature = "mimalloc",
+        feature = "arena_alloc"
+    )
+))]
+compile_error!("custom_alloc can't be combined with jemalloc, mimalloc or arena_alloc");
+
+#[cfg(feature = "jemalloc")]
+mod inner {
+    pub type Backend = tikv_jemallocator::Jemalloc;
+    pub const BACKEND: Backend = tikv_jemallocator::Jemalloc;
+    pub const NAME: &str = "jemalloc";
+}
+
+#[cfg(feature = "mimalloc")]
+mod inner {
+    pub type Backend = mimalloc::MiMalloc;
+    pub const BACKEND: Backend = mimalloc::MiMalloc;
+    pub const NAME: &str = "mimalloc";
+}
+
+#[cfg(feature = "arena_alloc")]
+mod inner {
+    pub type Backend = myalloc::Arena;
+    pub const BACKEND: Backend = myalloc::Arena;
+    pub const NAME: &str = "arena";
+}
+
+#[cfg(feature = "custom_alloc")]
+mod inner {
+    pub type Backend = myalloc::MyAlloc;
+    pub const BACKEND: Backend = myalloc::MyAlloc;
+    pub const NAME: &str = "system (traced)";
+}
+
+#[cfg(not(any(
+    feature = "jemalloc",
+    feature = "mimalloc",
+    feature = "arena_alloc",
+    feature = "custom_alloc"
+)))]
+mod inner {
+    pub type Backend = std::alloc::System;
+    pub const BACKEND: Backend = std::alloc::System;
+    pub const NAME: &str = "system";
+}
+
+pub use inner::NAME;
+
+#[cfg(feature = "count_alloc")]
+#[global_allocator]
+static GLOBAL: myalloc::Counting<inner::Backend> = myalloc::Counting::new(inner::BACKEND);
+
+#[cfg(not(feature = "count_alloc"))]
+#[global_allocator]
+static GLOBAL: inner::Backend = inner::BACKEND;
+
+/// allocator counters at the start of a run
+pub struct Mark {
+    #[cfg(feature = "count_alloc")]
+    counts: myalloc::Counts,
+}
+
+pub fn mark() -> Mark {
+    Mark {
+        #[cfg(feature = "count_alloc")]
+        counts: myalloc::counts(),
+    }
+}
+
+impl Mark {
+    /// allocator figures since the mark worth printing after a run, empty when there's none
+    pub fn summary(&self, elapsed: std::time::Duration) -> String {
+        #[cfg_attr(
+            not(any(feature = "count_alloc", feature = "arena_alloc")),
+            allow(unused_mut)
+        )]
+        let mut s = String::new();
+        #[cfg(feature = "count_alloc")]
+        {
+            s.push_str(&myalloc::counts().since(&self.counts).to_text(elapsed));
+            s.push('\n');
+        }
+        #[cfg(not(feature = "count_alloc"))]
+        let _ = elapsed;
+        #[cfg(feature = "arena_alloc")]
+        {
+            let a = myalloc::arena_stats();
+            s.push_str(&format!(
+                "arena holds {} chunks of {} bytes, {} taken in total\n",
+                a.live, a.chunk_size, a.total
+            ));
+        }
+        s
+    }
+}
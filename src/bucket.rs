@@ -0,0 +1,117 @@
+use crate::keys::mix;
+use mace::{Bucket, Mace, OpCode};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// how keys are spread over buckets, the route is a pure function of `(tid, idx)` so the load
+/// phase and the run phase always agree
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// all keys of a thread go to bucket `tid % n`
+    Thread,
+    /// keys are hashed over all buckets
+    Hash,
+    /// consecutive keys of a thread go to consecutive buckets
+    RoundRobin,
+}
+
+impl Policy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "thread" => Some(Self::Thread),
+            "hash" => Some(Self::Hash),
+            "round-robin" => Some(Self::RoundRobin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Thread => "thread",
+            Self::Hash => "hash",
+            Self::RoundRobin => "round-robin",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Buckets {
+    policy: Policy,
+    list: Vec<Bucket>,
+}
+
+impl Buckets {
+    pub fn name(i: usize) -> String {
+        if i == 0 {
+            "default".into()
+        } else {
+            format!("bucket_{i}")
+        }
+    }
+
+    pub fn create(db: &Mace, n: usize, policy: Policy) -> Result<Self, OpCode> {
+        let list = (0..n)
+            .map(|i| db.new_bucket(Self::name(i)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { policy, list })
+    }
+
+    pub fn open(db: &Mace, n: usize, policy: Policy) -> Result<Self, OpCode> {
+        let list = (0..n)
+            .map(|i| db.get_bucket(Self::name(i)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { policy, list })
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn all(&self) -> &[Bucket] {
+        &self.list
+    }
+
+    pub fn get(&self, i: usize) -> &Bucket {
+        &self.list[i]
+    }
+
+    /// index of the bucket which holds key `(tid, idx)`
+    pub fn route(&self, tid: usize, idx: usize) -> usize {
+        let n = self.list.len();
+        match self.policy {
+            Policy::Thread => tid % n,
+            Policy::Hash => (mix(((tid as u64) << 40) ^ idx as u64) % n as u64) as usize,
+            Policy::RoundRobin => (tid + idx) % n,
+        }
+    }
+}
+
+/// keep creating a bucket, writing `keys` entries into it and deleting it every `interval`
+/// until `stop` is set, returns how many buckets were churned
+pub fn churn(
+    db: Mace,
+    interval: Duration,
+    keys: usize,
+    value: Arc<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<usize> {
+    std::thread::spawn(move || {
+        let mut n = 0;
+        while !stop.load(Relaxed) {
+            let name = format!("churn_{n}");
+            let bkt = db.new_bucket(&name).unwrap();
+            let tx = bkt.begin().unwrap();
+            for i in 0..keys {
+                tx.put(format!("churn_{i}"), value.as_slice()).unwrap();
+            }
+            tx.commit().unwrap();
+            drop(bkt);
+            db.del_bucket(&name).unwrap();
+            n += 1;
+            std::thread::sleep(interval);
+        }
+        n
+    })
+}
@@ -0,0 +1,250 @@
+use std::io::Write;
+
+/// a lazily generated key space, every key is derived from `(tid, index)` on demand so the
+/// harness itself uses constant memory no matter how many keys are loaded
+///
+/// key layout is `key_{tid}_{index}` padded with `x` to `key_size`, `tid` and `index` are
+/// zero padded so keys in thread-major order are byte sorted, the visiting order of a thread
+/// is either sequential or a seeded pseudo-random permutation of its indices
+pub struct KeySpace {
+    key_size: usize,
+    counts: Vec<usize>,
+    perms: Option<Vec<Permutation>>,
+    tid_width: usize,
+    idx_width: usize,
+}
+
+/// number of decimal digits of `x`
+fn digits(x: usize) -> usize {
+    x.checked_ilog10().map_or(1, |x| x as usize + 1)
+}
+
+impl KeySpace {
+    /// every thread may use up to `scale` times its count of indices
+    pub fn new(
+        threads: usize,
+        total: usize,
+        scale: usize,
+        key_size: usize,
+        seed: u64,
+        shuffle: bool,
+    ) -> Self {
+        let mut counts = vec![total / threads; threads];
+        for cnt in counts.iter_mut().take(total % threads) {
+            *cnt += 1;
+        }
+        let max_idx = counts.iter().max().copied().unwrap_or(1).max(1) * scale - 1;
+        let perms = shuffle.then(|| {
+            counts
+                .iter()
+                .enumerate()
+                .map(|(tid, &n)| Permutation::new(n, seed ^ tid as u64))
+                .collect()
+        });
+        Self {
+            key_size,
+            counts,
+            perms,
+            tid_width: digits(threads - 1),
+            idx_width: digits(max_idx),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn count(&self, tid: usize) -> usize {
+        self.counts[tid]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// map a sequence number in `[0, total)` to `(tid, idx)` in thread-major order
+    pub fn locate(&self, seq: usize) -> (usize, usize) {
+        let q = self.counts[self.threads() - 1];
+        let r = self.total() % self.threads();
+        let head = r * (q + 1);
+        if seq < head {
+            (seq / (q + 1), seq % (q + 1))
+        } else {
+            let seq = seq - head;
+            (r + seq / q, seq % q)
+        }
+    }
+
+    /// length of a key before padding with `x`, must not exceed `key_size` or keys will collide
+    pub fn max_key_len(&self) -> usize {
+        "key__".len() + self.tid_width + self.idx_width
+    }
+
+    /// the index of the `pos`-th key visited by `tid`
+    pub fn index(&self, tid: usize, pos: usize) -> usize {
+        match &self.perms {
+            Some(p) => p[tid].apply(pos),
+            None => pos,
+        }
+    }
+
+    /// the inverse of [`Self::index`]
+    #[cfg(test)]
+    pub fn position(&self, tid: usize, idx: usize) -> usize {
+        match &self.perms {
+            Some(p) => p[tid].invert(idx),
+            None => idx,
+        }
+    }
+
+    /// write the key of `(tid, idx)` into `buf`, the buffer is reused to avoid allocation
+    pub fn fill(&self, tid: usize, idx: usize, buf: &mut Vec<u8>) {
+        buf.clear();
+        write!(
+            buf,
+            "key_{tid:0tw$}_{idx:0iw$}",
+            tw = self.tid_width,
+            iw = self.idx_width
+        )
+        .unwrap();
+        buf.resize(self.key_size, b'x');
+    }
+
+    /// recover `(tid, idx)` from a key produced by [`Self::fill`]
+    #[cfg(test)]
+    pub fn parse(&self, key: &[u8]) -> Option<(usize, usize)> {
+        let s = std::str::from_utf8(key.strip_prefix(b"key_")?).ok()?;
+        let s = s.trim_end_matches('x');
+        let (tid, idx) = s.split_once('_')?;
+        let (tid, idx) = (tid.parse().ok()?, idx.parse().ok()?);
+        (tid < self.threads() && idx < self.count(tid)).then_some((tid, idx))
+    }
+
+    pub fn prefix(&self, tid: usize) -> String {
+        format!("key_{tid:0tw$}_", tw = self.tid_width)
+    }
+}
+
+/// splitmix64 finalizer
+pub fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+const ROUNDS: usize = 4;
+
+/// a bijection on `[0, n)` built from a balanced feistel network over the smallest even
+/// power of two covering `n`, out of range outputs are cycle-walked back into the domain
+pub struct Permutation {
+    n: usize,
+    half_bits: u32,
+    keys: [u64; ROUNDS],
+}
+
+impl Permutation {
+    pub fn new(n: usize, seed: u64) -> Self {
+        let bits = usize::BITS - n.saturating_sub(1).leading_zeros();
+        let half_bits = bits.div_ceil(2).max(1);
+        let mut keys = [0u64; ROUNDS];
+        let mut s = mix(seed);
+        for k in keys.iter_mut() {
+            s = mix(s);
+            *k = s;
+        }
+        Self { n, half_bits, keys }
+    }
+
+    fn mask(&self) -> u64 {
+        (1u64 << self.half_bits) - 1
+    }
+
+    fn encrypt(&self, x: u64) -> u64 {
+        let mask = self.mask();
+        let (mut l, mut r) = (x >> self.half_bits, x & mask);
+        for k in self.keys {
+            (l, r) = (r, l ^ (mix(r ^ k) & mask));
+        }
+        (l << self.half_bits) | r
+    }
+
+    #[cfg(test)]
+    fn decrypt(&self, x: u64) -> u64 {
+        let mask = self.mask();
+        let (mut l, mut r) = (x >> self.half_bits, x & mask);
+        for k in self.keys.iter().rev() {
+            (l, r) = (r ^ (mix(l ^ k) & mask), l);
+        }
+        (l << self.half_bits) | r
+    }
+
+    pub fn apply(&self, pos: usize) -> usize {
+        debug_assert!(pos < self.n);
+        let mut x = self.encrypt(pos as u64);
+        while x >= self.n as u64 {
+            x = self.encrypt(x);
+        }
+        x as usize
+    }
+
+    #[cfg(test)]
+    pub fn invert(&self, idx: usize) -> usize {
+        debug_assert!(idx < self.n);
+        let mut x = self.decrypt(idx as u64);
+        while x >= self.n as u64 {
+            x = self.decrypt(x);
+        }
+        x as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeySpace;
+
+    #[test]
+    fn test_permutation() {
+        for n in [1, 2, 3, 17, 1000, 4097] {
+            let ks = KeySpace::new(1, n, 1, 16, 42, true);
+            let mut seen = vec![false; n];
+            for pos in 0..n {
+                let idx = ks.index(0, pos);
+                assert!(!seen[idx]);
+                seen[idx] = true;
+                assert_eq!(ks.position(0, idx), pos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let ks = KeySpace::new(3, 100, 1, 16, 0, false);
+        let mut buf = Vec::new();
+        ks.fill(2, 3, &mut buf);
+        assert_eq!(buf, b"key_2_03xxxxxxxx");
+        assert_eq!(ks.parse(&buf), Some((2, 3)));
+        assert_eq!(ks.parse(b"key_2_33xxxxxxxx"), None);
+        assert_eq!(ks.max_key_len(), 8);
+        assert_eq!(ks.prefix(1), "key_1_");
+
+        let mut seq = 0;
+        for tid in 0..ks.threads() {
+            for idx in 0..ks.count(tid) {
+                assert_eq!(ks.locate(seq), (tid, idx));
+                seq += 1;
+            }
+        }
+
+        // thread-major order is byte order
+        let ks = KeySpace::new(12, 1200, 3, 16, 0, false);
+        assert_eq!(ks.max_key_len(), 10);
+        let mut prev = Vec::new();
+        for seq in 0..ks.total() {
+            let (tid, idx) = ks.locate(seq);
+            ks.fill(tid, idx, &mut buf);
+            assert!(buf > prev);
+            std::mem::swap(&mut buf, &mut prev);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::bucket::Buckets;
+use crate::keys::{KeySpace, Permutation};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+pub struct LoadOptions {
+    pub threads: usize,
+    /// number of keys committed per transaction
+    pub batch: usize,
+    /// load keys in a seeded random order instead of byte sorted order
+    pub random: bool,
+    pub seed: u64,
+}
+
+pub struct LoadStat {
+    pub keys: usize,
+    pub duration: Duration,
+}
+
+impl LoadStat {
+    pub fn ops(&self) -> usize {
+        (self.keys as f64 / self.duration.as_secs_f64()) as usize
+    }
+}
+
+/// populate `buckets` with every key of `keys`, the key space is split into contiguous ranges
+/// of the load sequence and each loader commits its range in batches, progress is printed to
+/// stdout once per second since stderr is reserved for the result line
+pub fn load(buckets: &Buckets, keys: &Arc<KeySpace>, value: &[u8], opt: &LoadOptions) -> LoadStat {
+    let total = keys.total();
+    let perm = Arc::new(Permutation::new(total, !opt.seed));
+    let loaded = Arc::new(AtomicUsize::new(0));
+    let value = Arc::new(value.to_vec());
+    let per_loader = total.div_ceil(opt.threads);
+    let start = Instant::now();
+
+    let h: Vec<_> = (0..opt.threads)
+        .map(|lid| {
+            let db = buckets.clone();
+            let keys = keys.clone();
+            let perm = perm.clone();
+            let loaded = loaded.clone();
+            let value = value.clone();
+            let batch = opt.batch;
+            let random = opt.random;
+            let range = (lid * per_loader).min(total)..((lid + 1) * per_loader).min(total);
+
+            std::thread::spawn(move || {
+                let _pin = crate::affinity::bind(lid);
+                let mut key = Vec::new();
+                let mut pending = Vec::with_capacity(batch);
+                let mut seqs = range.peekable();
+                while seqs.peek().is_some() {
+                    // a batch may span buckets, commit each bucket's share in turn since a
+                    // thread must not keep several transactions open at the same time
+                    pending.clear();
+                    for seq in seqs.by_ref().take(batch) {
+                        let seq = if random { perm.apply(seq) } else { seq };
+                        let (tid, idx) = keys.locate(seq);
+                        pending.push((db.route(tid, idx), tid, idx));
+                    }
+                    pending.sort_unstable_by_key(|x| x.0);
+                    for group in pending.chunk_by(|x, y| x.0 == y.0) {
+                        let tx = db.get(group[0].0).begin().unwrap();
+                        for &(_, tid, idx) in group {
+                            keys.fill(tid, idx, &mut key);
+                            tx.put(&key, value.as_slice()).unwrap();
+                        }
+                        tx.commit().unwrap();
+                    }
+                    loaded.fetch_add(pending.len(), Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    let mut last = (start, 0);
+    while !h.iter().all(|x| x.is_finished()) {
+        std::thread::sleep(Duration::from_millis(100));
+        let now = Instant::now();
+        if now.duration_since(last.0) < Duration::from_secs(1) {
+            continue;
+        }
+        let cur = loaded.load(Relaxed);
+        let ops = (cur - last.1) as f64 / now.duration_since(last.0).as_secs_f64();
+        println!(
+            "load {}/{} ({:.1}%) {} ops",
+            cur,
+            total,
+            cur as f64 * 100.0 / total as f64,
+            ops as usize
+        );
+        last = (now, cur);
+    }
+
+    for x in h {
+        x.join().unwrap();
+    }
+
+    LoadStat {
+        keys: loaded.load(Relaxed),
+        duration: start.elapsed(),
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// describes the dataset persisted in a kept database, it's stored beside mace's own files as
+/// plain `name=value` lines so a later `--reuse` run can check it loads what it expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub keys: usize,
+    pub threads: usize,
+    pub key_size: usize,
+    pub value_size: usize,
+    pub seed: u64,
+    pub buckets: usize,
+    pub bucket_policy: String,
+}
+
+impl Manifest {
+    const NAME: &'static str = "kv_bench.manifest";
+
+    fn path(root: &Path) -> PathBuf {
+        root.join(Self::NAME)
+    }
+
+    fn fields(&self) -> [(&'static str, String); 7] {
+        [
+            ("keys", self.keys.to_string()),
+            ("threads", self.threads.to_string()),
+            ("key_size", self.key_size.to_string()),
+            ("value_size", self.value_size.to_string()),
+            ("seed", self.seed.to_string()),
+            ("buckets", self.buckets.to_string()),
+            ("bucket_policy", self.bucket_policy.clone()),
+        ]
+    }
+
+    pub fn exists(root: &Path) -> bool {
+        Self::path(root).exists()
+    }
+
+    pub fn load(root: &Path) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(Self::path(root))?;
+        let get = |name: &str| -> Result<&str, Error> {
+            s.lines()
+                .filter_map(|l| l.split_once('='))
+                .find(|(k, _)| k.trim() == name)
+                .map(|(_, v)| v.trim())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("missing {name}")))
+        };
+        let num = |name: &str| -> Result<u64, Error> {
+            get(name)?
+                .parse()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("bad {name}: {e}")))
+        };
+        Ok(Self {
+            keys: num("keys")? as usize,
+            threads: num("threads")? as usize,
+            key_size: num("key_size")? as usize,
+            value_size: num("value_size")? as usize,
+            seed: num("seed")?,
+            buckets: num("buckets")? as usize,
+            bucket_policy: get("bucket_policy")?.to_string(),
+        })
+    }
+
+    /// write to a temporary file then rename it, a crash never leaves a partial manifest
+    pub fn store(&self, root: &Path) -> Result<(), Error> {
+        let tmp = root.join(format!("{}.tmp", Self::NAME));
+        let s: String = self
+            .fields()
+            .iter()
+            .map(|(k, v)| format!("{k}={v}\n"))
+            .collect();
+        std::fs::write(&tmp, s)?;
+        std::fs::rename(tmp, Self::path(root))
+    }
+
+    /// fields which differ from `other`, formatted as `name: self != other`, the seed only
+    /// orders the keys and is recorded for reference
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        self.fields()
+            .iter()
+            .zip(other.fields())
+            .filter(|(a, b)| a.0 != "seed" && a.1 != b.1)
+            .map(|(a, b)| format!("{}: {} != {}", a.0, a.1, b.1))
+            .collect()
+    }
+}
@@ -0,0 +1,168 @@
+use coreid::SchedStat;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+/// ops between two samples of the current core, also the granularity of progress
+const SAMPLE: usize = 1024;
+
+/// counts the ops of a worker and tracks where it runs, every [`SAMPLE`] ops the progress is
+/// published and the current core sampled, the scheduler accounting of the thread is taken at
+/// both ends of the run
+pub struct Probe {
+    enabled: bool,
+    progress: Arc<AtomicUsize>,
+    worker: usize,
+    pinned: bool,
+    tid: usize,
+    ops: usize,
+    start: Instant,
+    before: Option<SchedStat>,
+    /// samples seen on each cpu, indexed by cpu id
+    cores: Vec<usize>,
+    last: Option<usize>,
+    moves: usize,
+}
+
+impl Probe {
+    /// ops are added to `progress`, a disabled probe only counts them and [`Probe::finish`]
+    /// returns `None`
+    pub fn new(worker: usize, pinned: bool, enabled: bool, progress: Arc<AtomicUsize>) -> Self {
+        let tid = coreid::gettid();
+        let mut p = Self {
+            enabled,
+            progress,
+            worker,
+            pinned,
+            tid,
+            ops: 0,
+            start: Instant::now(),
+            before: None,
+            cores: Vec::new(),
+            last: None,
+            moves: 0,
+        };
+        if enabled {
+            p.before = coreid::sched_stat(tid)
+                .map_err(|e| log::warn!("can't read schedstat of thread {tid}, {e}"))
+                .ok();
+            p.sample();
+        }
+        p
+    }
+
+    #[inline]
+    pub fn tick(&mut self) {
+        self.ops += 1;
+        if self.ops.is_multiple_of(SAMPLE) {
+            self.progress.fetch_add(SAMPLE, Relaxed);
+            if self.enabled {
+                self.sample();
+            }
+        }
+    }
+
+    fn sample(&mut self) {
+        let Some(cpu) = coreid::current_core() else {
+            return;
+        };
+        if cpu >= self.cores.len() {
+            self.cores.resize(cpu + 1, 0);
+        }
+        self.cores[cpu] += 1;
+        if self.last.is_some_and(|x| x != cpu) {
+            self.moves += 1;
+        }
+        self.last = Some(cpu);
+    }
+
+    pub fn finish(mut self) -> Option<ThreadSched> {
+        self.progress.fetch_add(self.ops % SAMPLE, Relaxed);
+        if !self.enabled {
+            return None;
+        }
+        self.sample();
+        let elapsed = self.start.elapsed();
+        let stat = match (self.before, coreid::sched_stat(self.tid)) {
+            (Some(b), Ok(a)) => Some(a.since(&b)),
+            _ => None,
+        };
+        Some(ThreadSched {
+            worker: self.worker,
+            tid: self.tid,
+            pinned: self.pinned,
+            elapsed,
+            cores: self
+                .cores
+                .iter()
+                .enumerate()
+                .filter(|x| *x.1 > 0)
+                .map(|(c, &n)| (c, n))
+                .collect(),
+            moves: self.moves,
+            stat,
+        })
+    }
+}
+
+pub struct ThreadSched {
+    pub worker: usize,
+    pub tid: usize,
+    pub pinned: bool,
+    pub elapsed: Duration,
+    /// `(cpu, samples)` of every cpu the worker was seen on
+    pub cores: Vec<(usize, usize)>,
+    /// core changes between two consecutive samples, a lower bound of migrations
+    pub moves: usize,
+    /// scheduler accounting over the run, `None` if the kernel doesn't provide it
+    pub stat: Option<SchedStat>,
+}
+
+impl ThreadSched {
+    /// share of the wall time the worker spent on a cpu
+    pub fn utilization(&self) -> Option<f64> {
+        let s = self.stat?;
+        Some(s.run.as_secs_f64() / self.elapsed.as_secs_f64().max(f64::EPSILON))
+    }
+
+    /// the worker spent more time waiting for a cpu than running
+    pub fn starved(&self) -> bool {
+        self.stat.is_some_and(|s| s.wait > s.run)
+    }
+}
+
+/// print one line per worker, followed by a warning for each worker whose pinning didn't hold
+/// or which was starved
+pub fn report(x: &[ThreadSched]) {
+    let opt = |x: Option<u64>| x.map_or("-".to_string(), |x| x.to_string());
+    for t in x {
+        let cores: Vec<_> = t.cores.iter().map(|(c, n)| format!("{c}:{n}")).collect();
+        println!(
+            "sched worker {} tid {} cores [{}] moves {} migrations {} run {}ms wait {}ms util {}",
+            t.worker,
+            t.tid,
+            cores.join(" "),
+            t.moves,
+            opt(t.stat.and_then(|s| s.migrations)),
+            t.stat.map_or(0, |s| s.run.as_millis()),
+            t.stat.map_or(0, |s| s.wait.as_millis()),
+            t.utilization()
+                .map_or("-".to_string(), |u| format!("{:.1}%", u * 100.0)),
+        );
+    }
+    for t in x {
+        if t.pinned && t.cores.len() > 1 {
+            println!(
+                "warning: worker {} is pinned but ran on {} cores",
+                t.worker,
+                t.cores.len()
+            );
+        }
+        if t.starved() {
+            println!(
+                "warning: worker {} waited longer for a cpu than it ran",
+                t.worker
+            );
+        }
+    }
+}
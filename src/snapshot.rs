@@ -0,0 +1,284 @@
+use crate::bucket::Buckets;
+use crate::keys::KeySpace;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Barrier};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// value byte written by the baseline phase, the load phase writes `b'0'`
+pub const BASELINE: u8 = b'1';
+/// value byte written while long readers are pinning their views
+pub const PINNED: u8 = b'2';
+
+/// the writer of the snapshot scenario, each op either overwrites an existing key or inserts a
+/// fresh one past the loaded key space
+pub struct Writer<'a> {
+    pub db: &'a Buckets,
+    pub keys: &'a KeySpace,
+    pub tid: usize,
+    pub insert_ratio: u8,
+    pub val: &'a [u8],
+    /// next fresh index to insert
+    pub fresh: usize,
+    pub key: Vec<u8>,
+}
+
+impl Writer<'_> {
+    /// write once and return the bucket written to
+    pub fn write(&mut self, idx: usize) -> usize {
+        let idx = if rand::random_range(0..100) < self.insert_ratio {
+            self.fresh += 1;
+            self.fresh - 1
+        } else {
+            idx
+        };
+        self.keys.fill(self.tid, idx, &mut self.key);
+        let b = self.db.route(self.tid, idx);
+        let tx = self.db.get(b).begin().unwrap();
+        tx.upsert(&self.key, self.val).unwrap();
+        tx.commit().unwrap();
+        b
+    }
+}
+
+pub struct Usage {
+    pub rss: u64,
+    pub disk: u64,
+}
+
+impl Usage {
+    pub fn now(root: &Path) -> Self {
+        Self {
+            rss: rss(),
+            disk: disk_usage(root),
+        }
+    }
+
+    /// `(rss, disk)` growth in MB since `before`
+    pub fn growth(&self, before: &Self) -> (f64, f64) {
+        let mb = |a: u64, b: u64| (a as f64 - b as f64) / (1 << 20) as f64;
+        (mb(self.rss, before.rss), mb(self.disk, before.disk))
+    }
+}
+
+/// resident set size in bytes, 0 if unavailable
+fn rss() -> u64 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|s| {
+            s.lines()
+                .find_map(|l| l.strip_prefix("VmRSS:"))
+                .and_then(|l| l.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        })
+        .map_or(0, |kb| kb << 10)
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(dir) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    dir.flatten()
+        .map(|e| match e.metadata() {
+            Ok(m) if m.is_dir() => disk_usage(&e.path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// run one writer phase without long readers, the result is the reference of the pinned phase
+pub fn baseline(
+    db: &Buckets,
+    keys: &Arc<KeySpace>,
+    insert_ratio: u8,
+    value_size: usize,
+) -> (usize, Duration) {
+    let val = Arc::new(vec![BASELINE; value_size]);
+    let start = Instant::now();
+    let h: Vec<_> = (0..keys.threads())
+        .map(|tid| {
+            let db = db.clone();
+            let keys = keys.clone();
+            let val = val.clone();
+            std::thread::spawn(move || {
+                let _pin = crate::affinity::bind(tid);
+                let mut w = Writer {
+                    db: &db,
+                    keys: &keys,
+                    tid,
+                    insert_ratio,
+                    val: &val,
+                    fresh: keys.count(tid),
+                    key: Vec::new(),
+                };
+                for pos in 0..keys.count(tid) {
+                    w.write(keys.index(tid, pos));
+                }
+                keys.count(tid)
+            })
+        })
+        .collect();
+    let total = h.into_iter().map(|x| x.join().unwrap()).sum();
+    (total, start.elapsed())
+}
+
+pub struct ReaderStat {
+    pub scans: usize,
+    pub entries: usize,
+    /// every scan saw the same entries and none of them was written after the view was opened
+    pub stable: bool,
+    /// the first [`MAX_DIFFS`] differences seen
+    pub diffs: Vec<Diff>,
+}
+
+/// differences kept per reader
+pub const MAX_DIFFS: usize = 8;
+
+/// an entry a scan saw differently from the first scan of its reader
+pub struct Diff {
+    /// counted from 0, the first scan only differs by entries written after the view was opened
+    pub scan: usize,
+    pub key: Vec<u8>,
+    pub kind: DiffKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    /// seen by the first scan only
+    Missing,
+    /// not seen by the first scan
+    Extra,
+    /// the value isn't the one seen by the first scan
+    Changed,
+    /// the value was written after the view was opened
+    Pinned,
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            DiffKind::Missing => "missing",
+            DiffKind::Extra => "extra",
+            DiffKind::Changed => "changed",
+            DiffKind::Pinned => "written after the view was opened",
+        };
+        write!(
+            f,
+            "scan {} {} {}",
+            self.scan,
+            String::from_utf8_lossy(&self.key),
+            kind
+        )
+    }
+}
+
+impl ReaderStat {
+    fn diff(&mut self, scan: usize, key: &[u8], kind: DiffKind) {
+        self.stable = false;
+        if self.diffs.len() < MAX_DIFFS {
+            self.diffs.push(Diff {
+                scan,
+                key: key.to_vec(),
+                kind,
+            });
+        }
+    }
+}
+
+/// readers which open a view on every bucket before the writers start and keep scanning them
+/// until stopped, sleeping `delay` between entries to emulate a slow consumer, stopping waits
+/// for one entry at most
+///
+/// every scan is compared entry by entry with the first one, which is kept in memory
+pub struct LongReaders {
+    stop: Arc<AtomicBool>,
+    h: Vec<JoinHandle<ReaderStat>>,
+}
+
+impl LongReaders {
+    pub fn start(db: &Buckets, n: usize, delay: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let opened = Arc::new(Barrier::new(n + 1));
+        let h = (0..n)
+            .map(|_| {
+                let db = db.clone();
+                let stop = stop.clone();
+                let opened = opened.clone();
+                std::thread::spawn(move || {
+                    let views: Vec<_> = db.all().iter().map(|b| b.view().unwrap()).collect();
+                    opened.wait();
+                    let mut stat = ReaderStat {
+                        scans: 0,
+                        entries: 0,
+                        stable: true,
+                        diffs: Vec::new(),
+                    };
+                    // entries of the first scan per view, in key order
+                    let mut first: Vec<Vec<(Vec<u8>, u8)>> = Vec::new();
+                    'scan: loop {
+                        let mut cnt = 0;
+                        let mut seen = Vec::new();
+                        for (b, v) in views.iter().enumerate() {
+                            let mut pos = 0;
+                            let mut cur = Vec::new();
+                            for x in v.seek("key_") {
+                                // a partial scan isn't counted, its differences so far are
+                                if stop.load(Relaxed) {
+                                    break 'scan;
+                                }
+                                let (k, val) = (x.key(), x.val()[0]);
+                                cnt += 1;
+                                if val == PINNED {
+                                    stat.diff(stat.scans, k, DiffKind::Pinned);
+                                }
+                                match first.get(b) {
+                                    None => cur.push((k.to_vec(), val)),
+                                    Some(old) => {
+                                        while pos < old.len() && old[pos].0.as_slice() < k {
+                                            stat.diff(stat.scans, &old[pos].0, DiffKind::Missing);
+                                            pos += 1;
+                                        }
+                                        if pos < old.len() && old[pos].0 == k {
+                                            if old[pos].1 != val {
+                                                stat.diff(stat.scans, k, DiffKind::Changed);
+                                            }
+                                            pos += 1;
+                                        } else {
+                                            stat.diff(stat.scans, k, DiffKind::Extra);
+                                        }
+                                    }
+                                }
+                                if !delay.is_zero() {
+                                    std::thread::sleep(delay);
+                                }
+                            }
+                            if let Some(old) = first.get(b) {
+                                for x in &old[pos..] {
+                                    stat.diff(stat.scans, &x.0, DiffKind::Missing);
+                                }
+                            }
+                            seen.push(cur);
+                        }
+                        if first.is_empty() {
+                            first = seen;
+                        }
+                        stat.scans += 1;
+                        stat.entries += cnt;
+                        if stop.load(Relaxed) {
+                            break;
+                        }
+                    }
+                    stat
+                })
+            })
+            .collect();
+        opened.wait();
+        Self { stop, h }
+    }
+
+    pub fn stop(self) -> Vec<ReaderStat> {
+        self.stop.store(true, Relaxed);
+        self.h.into_iter().map(|x| x.join().unwrap()).collect()
+    }
+}
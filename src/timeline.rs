@@ -0,0 +1,111 @@
+use crate::snapshot::Usage;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub struct Point {
+    pub elapsed: Duration,
+    pub ops: usize,
+    pub usage: Usage,
+    #[cfg(feature = "custom_alloc")]
+    pub heap: myalloc::Snapshot,
+}
+
+/// samples progress and memory every `interval` on a background thread until stopped
+pub struct Sampler {
+    stop: Arc<AtomicBool>,
+    h: JoinHandle<Vec<Point>>,
+}
+
+impl Sampler {
+    pub fn start(root: PathBuf, ops: Arc<AtomicUsize>, start: Instant, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let h = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut r = Vec::new();
+                loop {
+                    r.push(Point {
+                        elapsed: start.elapsed(),
+                        ops: ops.load(Relaxed),
+                        usage: Usage::now(&root),
+                        #[cfg(feature = "custom_alloc")]
+                        heap: myalloc::snapshot(),
+                    });
+                    if stop.load(Relaxed) {
+                        break;
+                    }
+                    // unparked early on stop, spurious wakeups only make a point early
+                    std::thread::park_timeout(interval);
+                }
+                r
+            })
+        };
+        Self { stop, h }
+    }
+
+    /// stop sampling, one last point is taken
+    pub fn stop(self) -> Vec<Point> {
+        self.stop.store(true, Relaxed);
+        self.h.thread().unpark();
+        self.h.join().unwrap()
+    }
+}
+
+/// write csv rows of `elapsed_ms,ops,ops_per_sec,rss,disk`, with custom_alloc followed by the
+/// traced live bytes and the live bytes of the `top` callsites, which are listed in
+/// `<path>.sites` since they span several lines
+pub fn write(path: &str, points: &[Point], top: usize) -> std::io::Result<()> {
+    let mut s = String::from("elapsed_ms,ops,ops_per_sec,rss,disk");
+    #[cfg(feature = "custom_alloc")]
+    let heap = {
+        let snaps: Vec<_> = points.iter().map(|x| x.heap.clone()).collect();
+        let t = myalloc::timeline(&snaps, top);
+        s.push_str(",heap_live");
+        let mut sites = String::new();
+        for (i, x) in t.sites.iter().enumerate() {
+            let _ = write!(s, ",site_{i}");
+            let _ = writeln!(sites, "site_{i}");
+            for l in x.lines() {
+                let _ = writeln!(sites, "    {l}");
+            }
+        }
+        std::fs::write(format!("{path}.sites"), sites)?;
+        t
+    };
+    #[cfg(not(feature = "custom_alloc"))]
+    let _ = top;
+    s.push('\n');
+
+    for (i, p) in points.iter().enumerate() {
+        let (ops, secs) = match i {
+            0 => (p.ops, p.elapsed.as_secs_f64()),
+            _ => (
+                p.ops - points[i - 1].ops,
+                (p.elapsed - points[i - 1].elapsed).as_secs_f64(),
+            ),
+        };
+        let rate = if secs > 0.0 { ops as f64 / secs } else { 0.0 };
+        let _ = write!(
+            s,
+            "{},{},{:.0},{},{}",
+            p.elapsed.as_millis(),
+            p.ops,
+            rate,
+            p.usage.rss,
+            p.usage.disk
+        );
+        #[cfg(feature = "custom_alloc")]
+        {
+            let _ = write!(s, ",{}", heap.total_live[i]);
+            for x in &heap.live[i] {
+                let _ = write!(s, ",{x}");
+            }
+        }
+        s.push('\n');
+    }
+    std::fs::write(path, s)
+}